@@ -0,0 +1,57 @@
+use std::fs::write;
+
+use crate::commands::ls_files;
+use crate::get_json_configuration::ReplaceRule;
+use crate::path_pattern::pattern_matches;
+use crate::read_text_file::read_text_file;
+
+// Returns the number of substitutions made by each rule.
+pub(crate) fn apply_replace_rules(
+    source_path: &str,
+    replace_rules: &[ReplaceRule],
+) -> Result<Vec<usize>, String> {
+    let mut substitution_counts: Vec<usize> = vec![0; replace_rules.len()];
+    if replace_rules.is_empty() {
+        return Ok(substitution_counts);
+    }
+    let files = match ls_files(source_path) {
+        Err(e) => return Err(e),
+        Ok(v) => v,
+    };
+    for relative_path in files {
+        let full_path = format!("{source_path}/{relative_path}");
+        let mut text: Option<String> = None;
+        let mut changed = false;
+        for (index, rule) in replace_rules.iter().enumerate() {
+            if !pattern_matches(&rule.files, &relative_path) {
+                continue;
+            }
+            if text.is_none() {
+                text = match read_text_file(&full_path) {
+                    Err(e) => return Err(e),
+                    Ok(None) => break,
+                    Ok(t) => t,
+                };
+            }
+            let current = text.as_ref().unwrap();
+            let count = rule.pattern.find_iter(current).count();
+            if count == 0 {
+                continue;
+            }
+            substitution_counts[index] += count;
+            text = Some(
+                rule.pattern
+                    .replace_all(current, rule.replacement.as_str())
+                    .into_owned(),
+            );
+            changed = true;
+        }
+        if changed {
+            match write(&full_path, text.unwrap()) {
+                Err(_) => return Err(format!("Unable to write {full_path}")),
+                Ok(_) => {}
+            }
+        }
+    }
+    Ok(substitution_counts)
+}
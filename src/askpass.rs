@@ -0,0 +1,25 @@
+use std::env::{args, var};
+use std::process::ExitCode;
+
+pub(crate) const ASKPASS_USERNAME_VAR: &str = "YELLOW_CHAMELEON_ASKPASS_USERNAME";
+pub(crate) const ASKPASS_PASSWORD_VAR: &str = "YELLOW_CHAMELEON_ASKPASS_PASSWORD";
+
+// git runs this binary as its GIT_ASKPASS program, with the prompt as
+// the only argument, and reads the answer from stdout.
+// Returns None if this process was not started by git to answer a prompt.
+pub(crate) fn answer_askpass_prompt() -> Option<ExitCode> {
+    let password = match var(ASKPASS_PASSWORD_VAR) {
+        Err(_) => return None,
+        Ok(p) => p,
+    };
+    let prompt = args().nth(1).unwrap_or_default();
+    if prompt.starts_with("Username") {
+        match var(ASKPASS_USERNAME_VAR) {
+            Err(_) => return Some(ExitCode::FAILURE),
+            Ok(username) => println!("{username}"),
+        }
+    } else {
+        println!("{password}");
+    }
+    Some(ExitCode::SUCCESS)
+}
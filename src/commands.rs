@@ -1,7 +1,17 @@
-use std::fs::read_dir;
-use std::process::{Command, Stdio};
+use std::cell::RefCell;
+use std::env::current_exe;
+use std::fs::{
+    canonicalize, copy, create_dir, create_dir_all, read_dir, read_link, remove_dir_all,
+    remove_file, rename, set_permissions, symlink_metadata,
+};
+use std::io;
+use std::os::unix::fs::symlink;
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
 
-use chrono::offset::Utc;
+use crate::askpass::{ASKPASS_PASSWORD_VAR, ASKPASS_USERNAME_VAR};
+use crate::output::print_error;
+use crate::sync_error::{is_authentication_failure, is_push_rejection, SyncError};
 
 pub(crate) fn ls(directory: &str) -> Result<Vec<String>, String> {
     match read_dir(directory) {
@@ -32,139 +42,331 @@ pub(crate) fn ls(directory: &str) -> Result<Vec<String>, String> {
     }
 }
 
-pub(crate) fn mv(original_path: &str, move_to_dir: &str) -> Result<(), String> {
-    match Command::new("mv")
-        .arg(original_path)
-        .arg(move_to_dir)
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status()
-    {
-        Err(_) => Err(String::from("Internal Error: failed to call mv")),
-        Ok(status) => {
-            if status.success() {
-                Ok(())
-            } else {
-                Err(String::from("mv returned nonzero exit code"))
+fn ls_files_impl(
+    root: &str,
+    relative_directory: &str,
+    output: &mut Vec<String>,
+) -> Result<(), String> {
+    let full_directory = if relative_directory.is_empty() {
+        String::from(root)
+    } else {
+        format!("{root}/{relative_directory}")
+    };
+    let entries = match ls(&full_directory) {
+        Err(e) => return Err(e),
+        Ok(v) => v,
+    };
+    for entry in entries {
+        let relative_path = if relative_directory.is_empty() {
+            entry
+        } else {
+            format!("{relative_directory}/{entry}")
+        };
+        let file_type = match symlink_metadata(format!("{root}/{relative_path}")) {
+            Err(_) => {
+                return Err(String::from(
+                    "Internal Error: unable to read metadata of a directory entry",
+                ))
+            }
+            Ok(m) => m.file_type(),
+        };
+        if file_type.is_dir() {
+            match ls_files_impl(root, &relative_path, output) {
+                Err(e) => return Err(e),
+                Ok(_) => {}
             }
+        } else if file_type.is_file() {
+            output.push(relative_path);
         }
     }
+    Ok(())
 }
 
-pub(crate) fn rm(path: &str) -> Result<(), String> {
-    match Command::new("rm")
-        .arg("-rf")
-        .arg(path)
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status()
-    {
-        Err(_) => Err(String::from("Internal Error: failed to call rm")),
-        Ok(_) => Ok(()),
+// Lists the regular files below root, relative to root.
+// Symbolic links are neither followed nor listed.
+pub(crate) fn ls_files(root: &str) -> Result<Vec<String>, String> {
+    let mut output: Vec<String> = Vec::new();
+    match ls_files_impl(root, "", &mut output) {
+        Err(e) => return Err(e),
+        Ok(_) => {}
     }
+    Ok(output)
 }
 
-pub(crate) fn mkdir(path: &str) -> Result<(), String> {
-    match Command::new("mkdir")
-        .arg(path)
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status()
-    {
-        Err(_) => Err(String::from("Internal Error: failed to call mkdir")),
-        Ok(status) => {
-            if status.success() {
-                Ok(())
-            } else {
-                Err(String::from("mkdir returned nonzero exit code"))
+fn copy_recursive(original_path: &Path, new_path: &Path) -> Result<(), String> {
+    let metadata = match symlink_metadata(original_path) {
+        Err(e) => return Err(format!("Unable to read {}: {e}", original_path.display())),
+        Ok(m) => m,
+    };
+    let file_type = metadata.file_type();
+    if file_type.is_symlink() {
+        let target = match read_link(original_path) {
+            Err(e) => return Err(format!("Unable to read {}: {e}", original_path.display())),
+            Ok(t) => t,
+        };
+        match symlink(&target, new_path) {
+            Err(e) => Err(format!("Unable to create {}: {e}", new_path.display())),
+            Ok(_) => Ok(()),
+        }
+    } else if file_type.is_dir() {
+        match create_dir(new_path) {
+            Err(e) => return Err(format!("Unable to create {}: {e}", new_path.display())),
+            Ok(_) => {}
+        }
+        let entries = match read_dir(original_path) {
+            Err(e) => return Err(format!("Unable to list {}: {e}", original_path.display())),
+            Ok(v) => v,
+        };
+        for entry in entries {
+            let entry = match entry {
+                Err(e) => return Err(format!("Unable to list {}: {e}", original_path.display())),
+                Ok(v) => v,
+            };
+            match copy_recursive(&entry.path(), &new_path.join(entry.file_name())) {
+                Err(e) => return Err(e),
+                Ok(_) => {}
             }
         }
+        match set_permissions(new_path, metadata.permissions()) {
+            Err(e) => Err(format!(
+                "Unable to set permissions of {}: {e}",
+                new_path.display()
+            )),
+            Ok(_) => Ok(()),
+        }
+    } else {
+        match copy(original_path, new_path) {
+            Err(e) => Err(format!(
+                "Unable to copy {} to {}: {e}",
+                original_path.display(),
+                new_path.display()
+            )),
+            Ok(_) => Ok(()),
+        }
     }
 }
 
-pub(crate) fn mkdir_all(path: &str) -> Result<(), String> {
-    match Command::new("mkdir")
-        .arg("-p")
-        .arg(path)
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status()
-    {
-        Err(_) => Err(String::from("Internal Error: failed to call mkdir")),
-        Ok(status) => {
-            if status.success() {
-                Ok(())
-            } else {
-                Err(String::from("mkdir returned nonzero exit code"))
+// Like mv, moves original_path into move_to_dir if it is an existing directory,
+// and otherwise renames original_path to move_to_dir.
+pub(crate) fn mv(original_path: &str, move_to_dir: &str) -> Result<(), String> {
+    let original = Path::new(original_path);
+    let target = Path::new(move_to_dir);
+    let new_path = if is_real_directory(target) {
+        match original.file_name() {
+            None => return Err(format!("Unable to move {original_path}: no file name")),
+            Some(name) => target.join(name),
+        }
+    } else {
+        target.to_path_buf()
+    };
+    match rename(original, &new_path) {
+        Ok(_) => Ok(()),
+        // rename cannot move a path to another filesystem.
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            match copy_recursive(original, &new_path) {
+                Err(e) => return Err(e),
+                Ok(_) => {}
             }
+            rm(original_path)
         }
+        Err(e) => Err(format!(
+            "Unable to move {original_path} to {}: {e}",
+            new_path.display()
+        )),
     }
 }
 
-fn git_config_impl(key: &str, value: &str) -> Result<(), String> {
-    match Command::new("git")
-        .arg("--no-pager")
-        .arg("config")
-        .arg("--global")
-        .arg(key)
-        .arg(value)
-        .stdout(Stdio::null())
-        .status()
-    {
-        Err(_) => Err(String::from("Internal Error: failed to call git config")),
-        Ok(status) => {
-            if status.success() {
-                Ok(())
+// Like cp -R, except that new_path must not exist yet.
+pub(crate) fn cp(original_path: &str, new_path: &str) -> Result<(), String> {
+    copy_recursive(Path::new(original_path), Path::new(new_path))
+}
+
+fn is_real_directory(path: &Path) -> bool {
+    match symlink_metadata(path) {
+        Err(_) => false,
+        Ok(m) => m.file_type().is_dir(),
+    }
+}
+
+// Like rm -rf, except that errors are reported.
+// Symbolic links are removed without being followed.
+pub(crate) fn rm(path: &str) -> Result<(), String> {
+    let result = match symlink_metadata(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => Err(e),
+        Ok(m) => {
+            if m.file_type().is_dir() {
+                remove_dir_all(path)
             } else {
-                Err(String::from("git config returned nonzero exit code"))
+                remove_file(path)
             }
         }
+    };
+    match result {
+        Err(e) => Err(format!("Unable to remove {path}: {e}")),
+        Ok(_) => Ok(()),
     }
 }
 
-pub(crate) fn git_config(name: &str, email: &str) -> Result<(), String> {
-    match git_config_impl("user.name", name) {
-        Err(e) => return Err(e),
-        Ok(_) => {}
+pub(crate) fn mkdir(path: &str) -> Result<(), String> {
+    match create_dir(path) {
+        Err(e) => Err(format!("Unable to create {path}: {e}")),
+        Ok(_) => Ok(()),
     }
-    match git_config_impl("user.email", email) {
+}
+
+pub(crate) fn mkdir_all(path: &str) -> Result<(), String> {
+    match create_dir_all(path) {
+        Err(e) => Err(format!("Unable to create {path}: {e}")),
+        Ok(_) => Ok(()),
+    }
+}
+
+// Like Command::status, except that stderr passes through the output layer,
+// since git may print remote URLs and messages from the server.
+// Also returns stderr, so that the cause of a failure can be told apart.
+fn run_with_filtered_stderr(command: &mut Command) -> io::Result<(ExitStatus, String)> {
+    let output = match command.stderr(Stdio::piped()).output() {
         Err(e) => return Err(e),
-        Ok(_) => {}
+        Ok(o) => o,
+    };
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    if !stderr.trim_end().is_empty() {
+        print_error(stderr.trim_end());
+    }
+    Ok((output.status, stderr))
+}
+
+fn status_with_filtered_stderr(command: &mut Command) -> io::Result<ExitStatus> {
+    match run_with_filtered_stderr(command) {
+        Err(e) => Err(e),
+        Ok((status, _)) => Ok(status),
     }
-    return Ok(());
 }
 
-pub(crate) fn git_clone(repo_url: &str, pat: &str) -> Result<(), String> {
-    match Command::new("git")
+pub(crate) struct GitCredentials<'a> {
+    pub username: Option<&'a str>,
+    pub pat: Option<&'a str>,
+    pub ssh_command: Option<&'a str>,
+}
+
+thread_local! {
+    // Passed to every git command, since any command run in a partial clone
+    // may need to fetch missing objects from the destination repository.
+    static GIT_CREDENTIAL_ENV: RefCell<Vec<(String, String)>> = const { RefCell::new(Vec::new()) };
+}
+
+// The PAT is handed to git through this binary acting as GIT_ASKPASS,
+// so it never appears in a URL, in process arguments, or in .git/config.
+pub(crate) fn set_git_credentials(credentials: &GitCredentials) -> Result<(), String> {
+    let mut env: Vec<(String, String)> = Vec::new();
+    if let Some(pat) = credentials.pat {
+        let askpass = match current_exe() {
+            Err(_) => {
+                return Err(String::from(
+                    "Internal Error: unable to locate the yellow-chameleon binary",
+                ))
+            }
+            Ok(p) => p,
+        };
+        env.push((
+            String::from("GIT_ASKPASS"),
+            askpass.to_string_lossy().into_owned(),
+        ));
+        env.push((String::from("GIT_TERMINAL_PROMPT"), String::from("0")));
+        env.push((String::from(ASKPASS_PASSWORD_VAR), String::from(pat)));
+        if let Some(username) = credentials.username {
+            env.push((String::from(ASKPASS_USERNAME_VAR), String::from(username)));
+        }
+        // Credential helpers configured on the runner would be asked first.
+        env.push((String::from("GIT_CONFIG_COUNT"), String::from("1")));
+        env.push((
+            String::from("GIT_CONFIG_KEY_0"),
+            String::from("credential.helper"),
+        ));
+        env.push((String::from("GIT_CONFIG_VALUE_0"), String::new()));
+    }
+    if let Some(ssh_command) = credentials.ssh_command {
+        env.push((String::from("GIT_SSH_COMMAND"), String::from(ssh_command)));
+    }
+    GIT_CREDENTIAL_ENV.with(|cell| *cell.borrow_mut() = env);
+    Ok(())
+}
+
+fn git_command() -> Command {
+    let mut command = Command::new("git");
+    GIT_CREDENTIAL_ENV.with(|cell| {
+        for (key, value) in cell.borrow().iter() {
+            command.env(key, value);
+        }
+    });
+    command
+}
+
+pub(crate) fn git_clone(repo_url: &str, directory: &str) -> Result<(), SyncError> {
+    let mut command = git_command();
+    command
         .arg("--no-pager")
         .arg("clone")
         .arg("--filter=tree:0")
         .arg(repo_url)
-        .arg("destination")
-        .stdout(Stdio::null())
-        .env("GH_TOKEN", pat)
-        .status()
-    {
-        Err(_) => Err(String::from("Internal Error: unable to call git clone")),
-        Ok(status) => {
+        .arg(directory)
+        .stdout(Stdio::null());
+    match run_with_filtered_stderr(&mut command) {
+        Err(_) => Err(SyncError::Internal(String::from(
+            "Internal Error: unable to call git clone",
+        ))),
+        Ok((status, stderr)) => {
             if status.success() {
                 Ok(())
+            } else if is_authentication_failure(&stderr) {
+                Err(SyncError::Authentication(String::from(
+                    "The destination repository rejected the credentials",
+                )))
             } else {
-                Err(String::from("git clone returned a nonzero exit code"))
+                Err(SyncError::Clone(String::from(
+                    "git clone returned a nonzero exit code",
+                )))
             }
         }
     }
 }
 
-pub(crate) fn git_add_all(cwd: &str) -> Result<(), String> {
-    match Command::new("git")
+pub(crate) fn git_checkout_branch(cwd: &str, branch: &str) -> Result<(), String> {
+    let remote_ref = format!("refs/remotes/origin/{branch}");
+    let mut args: Vec<&str> = vec!["checkout"];
+    match git_output(cwd, &["rev-parse", "--verify", "--quiet", &remote_ref]) {
+        Err(_) => args.extend(["-b", branch]),
+        Ok(_) => args.extend(["-B", branch, &remote_ref]),
+    }
+    match git_command()
         .arg("--no-pager")
-        .arg("add")
-        .arg("-A")
+        .args(&args)
         .current_dir(cwd)
         .stdout(Stdio::null())
+        .stderr(Stdio::null())
         .status()
     {
+        Err(_) => Err(String::from("Internal Error: unable to call git checkout")),
+        Ok(status) => {
+            if status.success() {
+                Ok(())
+            } else {
+                Err(format!("Unable to check out destination branch {branch}"))
+            }
+        }
+    }
+}
+
+pub(crate) fn git_add_all(cwd: &str) -> Result<(), String> {
+    match status_with_filtered_stderr(
+        git_command()
+            .arg("--no-pager")
+            .arg("add")
+            .arg("-A")
+            .current_dir(cwd)
+            .stdout(Stdio::null()),
+    ) {
         Err(_) => return Err(String::from("Internal Error: unable to call git add")),
         Ok(status) => {
             if status.success() {
@@ -176,60 +378,171 @@ pub(crate) fn git_add_all(cwd: &str) -> Result<(), String> {
     }
 }
 
+pub(crate) enum ChangeKind {
+    Added,
+    Modified,
+    Deleted,
+    Renamed,
+}
+
+pub(crate) struct FileChange {
+    pub kind: ChangeKind,
+    pub path: String,
+    // Only set for renamed files
+    pub previous_path: Option<String>,
+    // None for binary files
+    pub lines_added: Option<usize>,
+    pub lines_deleted: Option<usize>,
+}
+
 pub(crate) enum GitDiffResult {
     NoChanges,
-    Changes,
+    Changes(Vec<FileChange>),
 }
 
-pub(crate) fn git_diff(cwd: &str) -> Result<GitDiffResult, String> {
-    match Command::new("git")
+fn git_diff_output(cwd: &str, format_flag: &str, revisions: &[&str]) -> Result<String, String> {
+    match git_command()
         .arg("--no-pager")
         .arg("diff")
-        .arg("HEAD")
-        .arg("--name-only")
+        .arg("-M")
+        .arg(format_flag)
+        .arg("-z")
+        .args(revisions)
         .current_dir(cwd)
         .output()
     {
-        Err(_) => return Err(String::from("Internal Error: unable to call git diff")),
+        Err(_) => Err(String::from("Internal Error: unable to call git diff")),
         Ok(output) => {
             if !(output.status.success()) {
                 return Err(String::from("git diff returned a nonzero exit code"));
             }
-            // If git diff returns any sequence of characters that contains a character
-            // that is not an ASCII control code or ASCII whitespace
-            // then we can assume that the current repository state is
-            // different than the most recent commit.
-            //
-            // If git diff returns a sequence not containing such a character,
-            // then we can assume that the current repository state is,
-            // the same as the most recent commit.
-            //
-            // In a UTF-8 byte sequence, we can search for characters that are not
-            // ASCII control codes or ASCII whitespace by checking if any byte
-            // in the sequence has a value greater than 32.
-            for byte in output.stdout {
-                if byte > 32 {
-                    return Ok(GitDiffResult::Changes);
-                }
+            match String::from_utf8(output.stdout) {
+                Err(_) => Err(String::from(
+                    "Internal Error: file name is not valid Unicode",
+                )),
+                Ok(s) => Ok(s),
             }
-            return Ok(GitDiffResult::NoChanges);
         }
     }
 }
 
-pub(crate) fn git_commit(cwd: &str) -> Result<(), String> {
-    let commit_message = Utc::now()
-        .format("Sync at %Y-%m-%d %H:%M:%S UTC")
-        .to_string();
-    match Command::new("git")
-        .arg("--no-pager")
-        .arg("commit")
-        .arg("-m")
-        .arg(commit_message)
-        .current_dir(cwd)
-        .stdout(Stdio::null())
-        .status()
-    {
+fn parse_line_count(s: &str) -> Option<usize> {
+    // Binary files are reported as -
+    s.parse().ok()
+}
+
+fn git_diff_impl(cwd: &str, revisions: &[&str]) -> Result<GitDiffResult, String> {
+    let name_status = match git_diff_output(cwd, "--name-status", revisions) {
+        Err(e) => return Err(e),
+        Ok(v) => v,
+    };
+    let numstat = match git_diff_output(cwd, "--numstat", revisions) {
+        Err(e) => return Err(e),
+        Ok(v) => v,
+    };
+    parse_diff_output(&name_status, &numstat)
+}
+
+// With -z every field is terminated by a NUL byte and paths are never quoted.
+fn parse_diff_output(name_status: &str, numstat: &str) -> Result<GitDiffResult, String> {
+    let mut changes: Vec<FileChange> = Vec::new();
+    let mut name_status_fields = name_status.split_terminator('\0').map(String::from);
+    while let Some(status) = name_status_fields.next() {
+        let first_path = match name_status_fields.next() {
+            None => return Err(String::from("Internal Error: unexpected git diff output")),
+            Some(p) => p,
+        };
+        let change = match status.chars().next() {
+            Some('A') => FileChange {
+                kind: ChangeKind::Added,
+                path: first_path,
+                previous_path: None,
+                lines_added: None,
+                lines_deleted: None,
+            },
+            Some('D') => FileChange {
+                kind: ChangeKind::Deleted,
+                path: first_path,
+                previous_path: None,
+                lines_added: None,
+                lines_deleted: None,
+            },
+            Some('R') => match name_status_fields.next() {
+                None => return Err(String::from("Internal Error: unexpected git diff output")),
+                Some(second_path) => FileChange {
+                    kind: ChangeKind::Renamed,
+                    path: second_path,
+                    previous_path: Some(first_path),
+                    lines_added: None,
+                    lines_deleted: None,
+                },
+            },
+            _ => FileChange {
+                kind: ChangeKind::Modified,
+                path: first_path,
+                previous_path: None,
+                lines_added: None,
+                lines_deleted: None,
+            },
+        };
+        changes.push(change);
+    }
+    // numstat lists the same files in the same order. For a rename the counts
+    // are followed by an empty path and then the old and new paths as separate fields.
+    let mut numstat_fields = numstat.split_terminator('\0');
+    for change in changes.iter_mut() {
+        let counts = match numstat_fields.next() {
+            None => return Err(String::from("Internal Error: unexpected git diff output")),
+            Some(c) => c,
+        };
+        let mut parts = counts.splitn(3, '\t');
+        change.lines_added = parse_line_count(parts.next().unwrap_or(""));
+        change.lines_deleted = parse_line_count(parts.next().unwrap_or(""));
+        if let ChangeKind::Renamed = change.kind {
+            numstat_fields.next();
+            numstat_fields.next();
+        }
+    }
+    if changes.is_empty() {
+        Ok(GitDiffResult::NoChanges)
+    } else {
+        Ok(GitDiffResult::Changes(changes))
+    }
+}
+
+pub(crate) fn git_diff(cwd: &str) -> Result<GitDiffResult, String> {
+    git_diff_impl(cwd, &["--cached", "HEAD"])
+}
+
+pub(crate) fn git_diff_since(cwd: &str, revision: &str) -> Result<GitDiffResult, String> {
+    git_diff_impl(cwd, &[revision, "HEAD"])
+}
+
+#[derive(Clone)]
+pub(crate) struct GitIdentity {
+    pub name: String,
+    pub email: String,
+}
+
+pub(crate) fn git_commit(
+    cwd: &str,
+    commit_message: &str,
+    author: &GitIdentity,
+    committer: &GitIdentity,
+) -> Result<(), String> {
+    match status_with_filtered_stderr(
+        git_command()
+            .arg("--no-pager")
+            .arg("commit")
+            .arg("-m")
+            .arg(commit_message)
+            .current_dir(cwd)
+            .env("GIT_AUTHOR_NAME", &author.name)
+            .env("GIT_AUTHOR_EMAIL", &author.email)
+            .env("GIT_COMMITTER_NAME", &committer.name)
+            .env("GIT_COMMITTER_EMAIL", &committer.email)
+            .stdout(Stdio::null()),
+    ) {
         Err(_) => return Err(String::from("Internal Error: unable to call git commit")),
         Ok(status) => {
             if status.success() {
@@ -241,23 +554,338 @@ pub(crate) fn git_commit(cwd: &str) -> Result<(), String> {
     }
 }
 
-pub(crate) fn git_push(cwd: &str, repo_url: &str, pat: &str) -> Result<(), String> {
-    match Command::new("git")
+pub(crate) fn git_push(
+    cwd: &str,
+    repo_url: &str,
+    branch: &str,
+    force: bool,
+) -> Result<(), SyncError> {
+    let force_args: &[&str] = if force { &["--force"] } else { &[] };
+    let mut command = git_command();
+    command
         .arg("--no-pager")
         .arg("push")
+        .args(force_args)
         .arg(repo_url)
+        .arg(format!("HEAD:refs/heads/{branch}"))
         .current_dir(cwd)
-        .stdout(Stdio::null())
-        .env("GH_TOKEN", pat)
-        .status()
-    {
-        Err(_) => Err(String::from("Internal Error: unable to call git push")),
-        Ok(status) => {
+        .stdout(Stdio::null());
+    match run_with_filtered_stderr(&mut command) {
+        Err(_) => Err(SyncError::Internal(String::from(
+            "Internal Error: unable to call git push",
+        ))),
+        Ok((status, stderr)) => {
             if status.success() {
                 Ok(())
+            } else if is_authentication_failure(&stderr) {
+                Err(SyncError::Authentication(String::from(
+                    "The destination repository rejected the credentials",
+                )))
+            } else if is_push_rejection(&stderr) {
+                Err(SyncError::PushRejected(format!(
+                    "The push was rejected because destination branch {branch} has changed"
+                )))
             } else {
-                Err(String::from("git push returned a nonzero exit code"))
+                Err(SyncError::Push(String::from(
+                    "git push returned a nonzero exit code",
+                )))
+            }
+        }
+    }
+}
+
+fn git_output(cwd: &str, args: &[&str]) -> Result<String, String> {
+    let command_name = format!("git {}", args[0]);
+    match git_command()
+        .arg("--no-pager")
+        .args(args)
+        .current_dir(cwd)
+        .output()
+    {
+        Err(_) => Err(format!("Internal Error: unable to call {command_name}")),
+        Ok(output) => {
+            if !(output.status.success()) {
+                return Err(format!("{command_name} returned a nonzero exit code"));
+            }
+            match String::from_utf8(output.stdout) {
+                Err(_) => Err(format!(
+                    "Internal Error: {command_name} output is not valid Unicode"
+                )),
+                Ok(s) => Ok(s),
             }
         }
     }
 }
+
+pub(crate) fn git_head(cwd: &str) -> Result<String, String> {
+    match git_output(cwd, &["rev-parse", "HEAD"]) {
+        Err(e) => Err(e),
+        Ok(s) => Ok(String::from(s.trim())),
+    }
+}
+
+// Commits created by a sync that preserves history carry this trailer,
+// naming the source commit they were created from.
+pub(crate) const SOURCE_COMMIT_TRAILER: &str = "Yellow-Chameleon-Source";
+
+pub(crate) fn git_last_synced_commit(cwd: &str) -> Result<Option<String>, String> {
+    let output = match git_output(
+        cwd,
+        &[
+            "log",
+            "-n",
+            "1",
+            &format!("--grep=^{SOURCE_COMMIT_TRAILER}: "),
+            &format!("--format=%(trailers:key={SOURCE_COMMIT_TRAILER},valueonly)"),
+        ],
+    ) {
+        Err(e) => return Err(e),
+        Ok(s) => s,
+    };
+    match output.lines().map(str::trim).find(|line| !line.is_empty()) {
+        None => Ok(None),
+        Some(sha) => Ok(Some(String::from(sha))),
+    }
+}
+
+pub(crate) fn git_is_ancestor(cwd: &str, ancestor: &str, descendant: &str) -> bool {
+    match git_command()
+        .arg("--no-pager")
+        .arg("merge-base")
+        .arg("--is-ancestor")
+        .arg(ancestor)
+        .arg(descendant)
+        .current_dir(cwd)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+    {
+        Err(_) => false,
+        Ok(status) => status.success(),
+    }
+}
+
+// Returns false if either revision does not exist.
+pub(crate) fn git_same_tree(cwd: &str, revision: &str, other_revision: &str) -> bool {
+    match git_command()
+        .arg("--no-pager")
+        .arg("rev-parse")
+        .arg(format!("{revision}^{{tree}}"))
+        .arg(format!("{other_revision}^{{tree}}"))
+        .current_dir(cwd)
+        .stderr(Stdio::null())
+        .output()
+    {
+        Err(_) => false,
+        Ok(output) => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let trees: Vec<&str> = stdout.lines().collect();
+            output.status.success() && trees.len() == 2 && trees[0] == trees[1]
+        }
+    }
+}
+
+pub(crate) struct SourceCommit {
+    pub sha: String,
+    // Committer date, in seconds since the UNIX epoch
+    pub timestamp: i64,
+    pub author_email: String,
+    pub subject: String,
+}
+
+pub(crate) fn git_list_commits(
+    cwd: &str,
+    since: Option<&str>,
+) -> Result<Vec<SourceCommit>, String> {
+    let range = match since {
+        None => String::from("HEAD"),
+        Some(sha) => format!("{sha}..HEAD"),
+    };
+    let mut args: Vec<&str> = vec![
+        "log",
+        "--reverse",
+        "--first-parent",
+        "--format=%H %ct %ae %s",
+    ];
+    if since.is_none() {
+        args.push("--max-count=1");
+    }
+    args.push(&range);
+    let output = match git_output(cwd, &args) {
+        Err(e) => return Err(e),
+        Ok(s) => s,
+    };
+    let mut commits: Vec<SourceCommit> = Vec::new();
+    for line in output.lines() {
+        let mut fields = line.splitn(4, ' ');
+        let sha = fields.next().unwrap_or("");
+        let timestamp = match fields.next().unwrap_or("").parse() {
+            Err(_) => return Err(String::from("Internal Error: unexpected git log output")),
+            Ok(t) => t,
+        };
+        commits.push(SourceCommit {
+            sha: String::from(sha),
+            timestamp: timestamp,
+            author_email: String::from(fields.next().unwrap_or("")),
+            subject: String::from(fields.next().unwrap_or("")),
+        });
+    }
+    Ok(commits)
+}
+
+pub(crate) fn git_current_branch(cwd: &str) -> Option<String> {
+    match git_output(cwd, &["symbolic-ref", "--quiet", "--short", "HEAD"]) {
+        Err(_) => None,
+        Ok(s) => Some(String::from(s.trim())),
+    }
+}
+
+pub(crate) fn git_export_commit(
+    repository: &str,
+    sha: &str,
+    output_directory: &str,
+) -> Result<(), String> {
+    let absolute_output = match canonicalize(output_directory) {
+        Err(_) => {
+            return Err(String::from(
+                "Internal Error: unable to resolve the export directory",
+            ))
+        }
+        Ok(p) => p,
+    };
+    let index_file = absolute_output.with_extension("index");
+    let prefix = format!("{}/", absolute_output.display());
+    for args in [
+        vec!["read-tree", sha],
+        vec!["checkout-index", "--all", "--force", "--prefix", &prefix],
+    ] {
+        match status_with_filtered_stderr(
+            git_command()
+                .arg("--no-pager")
+                .args(&args)
+                .current_dir(repository)
+                .env("GIT_INDEX_FILE", &index_file)
+                .stdout(Stdio::null()),
+        ) {
+            Err(_) => return Err(format!("Internal Error: unable to call git {}", args[0])),
+            Ok(status) => {
+                if !(status.success()) {
+                    return Err(format!("git {} returned a nonzero exit code", args[0]));
+                }
+            }
+        }
+    }
+    rm(&index_file.display().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Terminates every field with a NUL byte, as git diff -z does.
+    fn fields(fields: &[&str]) -> String {
+        fields.iter().map(|field| format!("{field}\0")).collect()
+    }
+
+    fn parse_changes(name_status: &[&str], numstat: &[&str]) -> Vec<FileChange> {
+        match parse_diff_output(&fields(name_status), &fields(numstat)).unwrap() {
+            GitDiffResult::NoChanges => Vec::new(),
+            GitDiffResult::Changes(changes) => changes,
+        }
+    }
+
+    #[test]
+    fn parses_every_kind_of_change() {
+        let changes = parse_changes(
+            &[
+                "A",
+                "added.txt",
+                "M",
+                "bin.dat",
+                "D",
+                "del.txt",
+                "M",
+                "mod.txt",
+                "R100",
+                "old.txt",
+                "new dir/new name.txt",
+            ],
+            &[
+                "1\t0\tadded.txt",
+                "-\t-\tbin.dat",
+                "0\t1\tdel.txt",
+                "1\t0\tmod.txt",
+                "0\t0\t",
+                "old.txt",
+                "new dir/new name.txt",
+            ],
+        );
+        assert_eq!(changes.len(), 5);
+        assert!(matches!(changes[0].kind, ChangeKind::Added));
+        assert_eq!(changes[0].path, "added.txt");
+        assert_eq!(
+            (changes[0].lines_added, changes[0].lines_deleted),
+            (Some(1), Some(0))
+        );
+        assert!(matches!(changes[1].kind, ChangeKind::Modified));
+        assert_eq!(
+            (changes[1].lines_added, changes[1].lines_deleted),
+            (None, None)
+        );
+        assert!(matches!(changes[2].kind, ChangeKind::Deleted));
+        assert_eq!(changes[2].path, "del.txt");
+        assert_eq!(changes[2].lines_deleted, Some(1));
+        assert!(matches!(changes[3].kind, ChangeKind::Modified));
+        assert_eq!(changes[3].lines_added, Some(1));
+        assert!(matches!(changes[4].kind, ChangeKind::Renamed));
+        assert_eq!(changes[4].path, "new dir/new name.txt");
+        assert_eq!(changes[4].previous_path.as_deref(), Some("old.txt"));
+        assert_eq!(
+            (changes[4].lines_added, changes[4].lines_deleted),
+            (Some(0), Some(0))
+        );
+    }
+
+    #[test]
+    fn counts_after_a_rename_belong_to_the_next_file() {
+        let changes = parse_changes(
+            &["R090", "a.txt", "b.txt", "M", "c.txt"],
+            &["2\t1\t", "a.txt", "b.txt", "5\t3\tc.txt"],
+        );
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].lines_added, Some(2));
+        assert_eq!(changes[1].path, "c.txt");
+        assert_eq!(
+            (changes[1].lines_added, changes[1].lines_deleted),
+            (Some(5), Some(3))
+        );
+    }
+
+    #[test]
+    fn paths_are_taken_verbatim() {
+        let changes = parse_changes(
+            &["A", "tab\there \"quoted\".txt"],
+            &["1\t0\ttab\there \"quoted\".txt"],
+        );
+        assert_eq!(changes[0].path, "tab\there \"quoted\".txt");
+        assert_eq!(changes[0].lines_added, Some(1));
+    }
+
+    #[test]
+    fn empty_output_is_no_changes() {
+        assert!(matches!(
+            parse_diff_output("", "").unwrap(),
+            GitDiffResult::NoChanges
+        ));
+    }
+
+    #[test]
+    fn truncated_output_is_an_error() {
+        assert!(parse_diff_output(
+            &fields(&["R100", "a.txt"]),
+            &fields(&["0\t0\t", "a.txt", "b.txt"])
+        )
+        .is_err());
+        assert!(parse_diff_output(&fields(&["M", "a.txt"]), "").is_err());
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::BTreeSet;
+
+use chrono::DateTime;
+
+use crate::commands::{FileChange, SourceCommit};
+
+pub(crate) const DEFAULT_COMMIT_MESSAGE: &str = "Sync at {timestamp}";
+
+// The commit-message input takes precedence over the destination configuration.
+pub(crate) fn select_commit_message_template<'a>(
+    environment_commit_message: &'a Option<String>,
+    destination_commit_message: &'a Option<String>,
+) -> &'a str {
+    match environment_commit_message {
+        Some(m) => m,
+        None => match destination_commit_message {
+            Some(m) => m,
+            None => DEFAULT_COMMIT_MESSAGE,
+        },
+    }
+}
+
+pub(crate) struct CommitMessageContext<'a> {
+    // The source commit being synced, if the source is a git repository
+    pub source_commit: Option<&'a SourceCommit>,
+    pub source_ref: Option<&'a str>,
+    // Seconds since the UNIX epoch
+    pub timestamp: i64,
+    pub changes: &'a [FileChange],
+    // The subject of a source commit may contain private information,
+    // so it is only published if this is set.
+    pub include_source_subject: bool,
+}
+
+fn changed_directories(changes: &[FileChange]) -> String {
+    let mut directories: BTreeSet<&str> = BTreeSet::new();
+    for change in changes {
+        for path in [Some(&change.path), change.previous_path.as_ref()]
+            .into_iter()
+            .flatten()
+        {
+            match path.split_once('/') {
+                None => directories.insert("."),
+                Some((directory, _)) => directories.insert(directory),
+            };
+        }
+    }
+    directories
+        .into_iter()
+        .map(|directory| format!("- {directory}"))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn require_source_commit<'a>(
+    context: &CommitMessageContext<'a>,
+    placeholder: &str,
+) -> Result<&'a SourceCommit, String> {
+    match context.source_commit {
+        None => Err(format!(
+            "The {{{placeholder}}} placeholder in commit-message requires the source to be a git repository"
+        )),
+        Some(commit) => Ok(commit),
+    }
+}
+
+fn expand_placeholder(placeholder: &str, context: &CommitMessageContext) -> Result<String, String> {
+    match placeholder {
+        "timestamp" => match DateTime::from_timestamp(context.timestamp, 0) {
+            None => Err(String::from("Internal Error: timestamp is out of range")),
+            Some(t) => Ok(t.format("%Y-%m-%d %H:%M:%S UTC").to_string()),
+        },
+        "source_sha" => match require_source_commit(context, placeholder) {
+            Err(e) => Err(e),
+            Ok(commit) => Ok(commit.sha.clone()),
+        },
+        "source_short_sha" => match require_source_commit(context, placeholder) {
+            Err(e) => Err(e),
+            Ok(commit) => Ok(commit.sha.chars().take(7).collect()),
+        },
+        "source_subject" => {
+            if !context.include_source_subject {
+                return Err(String::from(
+                    "The {source_subject} placeholder in commit-message requires include-source-subject to be true",
+                ));
+            }
+            match require_source_commit(context, placeholder) {
+                Err(e) => Err(e),
+                Ok(commit) => Ok(commit.subject.clone()),
+            }
+        }
+        "source_ref" => match context.source_ref {
+            None => Err(String::from(
+                "The {source_ref} placeholder in commit-message requires the source ref to be known",
+            )),
+            Some(source_ref) => Ok(String::from(source_ref)),
+        },
+        "changed_files_count" => Ok(context.changes.len().to_string()),
+        "changed_directories" => Ok(changed_directories(context.changes)),
+        _ => Err(format!(
+            "Unknown placeholder {{{placeholder}}} in commit-message"
+        )),
+    }
+}
+
+// Replaces every {placeholder} in the template. {{ and }} produce literal braces.
+pub(crate) fn render_commit_message(
+    template: &str,
+    context: &CommitMessageContext,
+) -> Result<String, String> {
+    let mut output = String::new();
+    let mut rest = template;
+    while let Some(index) = rest.find(['{', '}']) {
+        output.push_str(&rest[..index]);
+        let after = &rest[(index + 1)..];
+        if rest[index..].starts_with("{{") || rest[index..].starts_with("}}") {
+            output.push_str(&rest[index..(index + 1)]);
+            rest = &after[1..];
+            continue;
+        }
+        if rest[index..].starts_with('}') {
+            return Err(String::from("Unmatched } in commit-message"));
+        }
+        let end = match after.find('}') {
+            None => return Err(String::from("Unmatched { in commit-message")),
+            Some(e) => e,
+        };
+        match expand_placeholder(&after[..end], context) {
+            Err(e) => return Err(e),
+            Ok(value) => output.push_str(&value),
+        }
+        rest = &after[(end + 1)..];
+    }
+    output.push_str(rest);
+    if output.trim().is_empty() {
+        return Err(String::from("commit-message must not be empty"));
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::ChangeKind;
+
+    fn change(kind: ChangeKind, path: &str, previous_path: Option<&str>) -> FileChange {
+        FileChange {
+            kind: kind,
+            path: String::from(path),
+            previous_path: previous_path.map(String::from),
+            lines_added: Some(1),
+            lines_deleted: Some(0),
+        }
+    }
+
+    fn source_commit() -> SourceCommit {
+        SourceCommit {
+            sha: String::from("0123456789abcdef0123456789abcdef01234567"),
+            timestamp: 0,
+            author_email: String::from("dev@example.com"),
+            subject: String::from("Fix the widget"),
+        }
+    }
+
+    fn render(
+        template: &str,
+        commit: Option<&SourceCommit>,
+        include_source_subject: bool,
+    ) -> Result<String, String> {
+        let changes = [
+            change(ChangeKind::Added, "docs/a.md", None),
+            change(ChangeKind::Modified, "README.md", None),
+            change(ChangeKind::Renamed, "src/new.rs", Some("lib/old.rs")),
+        ];
+        render_commit_message(
+            template,
+            &CommitMessageContext {
+                source_commit: commit,
+                source_ref: Some("main"),
+                timestamp: 1700000000,
+                changes: &changes,
+                include_source_subject: include_source_subject,
+            },
+        )
+    }
+
+    #[test]
+    fn default_template() {
+        assert_eq!(
+            render(DEFAULT_COMMIT_MESSAGE, None, false).unwrap(),
+            "Sync at 2023-11-14 22:13:20 UTC"
+        );
+    }
+
+    #[test]
+    fn source_placeholders() {
+        let commit = source_commit();
+        assert_eq!(
+            render(
+                "{source_short_sha} {source_sha} {source_ref}",
+                Some(&commit),
+                false
+            )
+            .unwrap(),
+            "0123456 0123456789abcdef0123456789abcdef01234567 main"
+        );
+        assert_eq!(
+            render("Sync: {source_subject}", Some(&commit), true).unwrap(),
+            "Sync: Fix the widget"
+        );
+    }
+
+    #[test]
+    fn change_placeholders() {
+        assert_eq!(
+            render(
+                "{changed_files_count} files\n{changed_directories}",
+                None,
+                false
+            )
+            .unwrap(),
+            "3 files\n- .\n- docs\n- lib\n- src"
+        );
+    }
+
+    #[test]
+    fn escaped_braces() {
+        assert_eq!(
+            render("{{literal}} {changed_files_count}", None, false).unwrap(),
+            "{literal} 3"
+        );
+    }
+
+    #[test]
+    fn source_subject_requires_opt_in() {
+        let commit = source_commit();
+        assert!(render("{source_subject}", Some(&commit), false).is_err());
+    }
+
+    #[test]
+    fn source_sha_requires_source_commit() {
+        assert!(render("{source_sha}", None, false).is_err());
+    }
+
+    #[test]
+    fn invalid_templates() {
+        assert!(render("{unknown}", None, false).is_err());
+        assert!(render("{timestamp", None, false).is_err());
+        assert!(render("timestamp}", None, false).is_err());
+        assert!(render("  ", None, false).is_err());
+    }
+}
@@ -0,0 +1,36 @@
+use crate::commands::{ChangeKind, FileChange};
+
+fn describe_line_counts(change: &FileChange) -> String {
+    match (change.lines_added, change.lines_deleted) {
+        (Some(added), Some(deleted)) => format!("+{added} -{deleted}"),
+        _ => String::from("binary"),
+    }
+}
+
+// One line per file, followed by a line with the totals.
+pub(crate) fn describe_changes(changes: &[FileChange]) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let mut counts: [usize; 4] = [0; 4];
+    for change in changes {
+        let (index, label) = match change.kind {
+            ChangeKind::Added => (0, "added"),
+            ChangeKind::Modified => (1, "modified"),
+            ChangeKind::Deleted => (2, "deleted"),
+            ChangeKind::Renamed => (3, "renamed"),
+        };
+        counts[index] += 1;
+        let path = match &change.previous_path {
+            None => change.path.clone(),
+            Some(previous_path) => format!("{previous_path} -> {}", change.path),
+        };
+        lines.push(format!(
+            "  {label:<8} {:<14} {path}",
+            describe_line_counts(change)
+        ));
+    }
+    lines.push(format!(
+        "{} added, {} modified, {} deleted, {} renamed",
+        counts[0], counts[1], counts[2], counts[3]
+    ));
+    lines.join("\n")
+}
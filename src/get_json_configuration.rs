@@ -1,37 +1,69 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 use json::JsonValue;
+use regex::Regex;
 
+use crate::commands::GitIdentity;
+use crate::path_pattern::{literal_pattern, parse_pattern, PathPattern};
 use crate::read_json_file::read_json_file;
 use crate::sanitize_path::sanitize;
+use crate::scan_for_secrets::{builtin_secret_rules, SecretRule, SecretScanConfiguration};
+
+pub(crate) struct ReplaceRule {
+    pub files: PathPattern,
+    pub pattern: Regex,
+    pub replacement: String,
+}
 
 pub(crate) struct SourceConfiguration {
-    pub ignore_list: Vec<String>,
+    pub ignore_list: Vec<PathPattern>,
+    pub include_list: Option<Vec<PathPattern>>,
+    pub replace_rules: Vec<ReplaceRule>,
+    pub secret_scan: SecretScanConfiguration,
+    // Maps the lowercase email of a source commit author to their public identity
+    pub authors: HashMap<String, GitIdentity>,
+}
+
+// One element of the destinations array of the manifest
+pub(crate) struct ManifestDestination {
+    pub name: String,
+    pub repository: String,
+    pub host: Option<String>,
+    pub source_path: Option<String>,
+    pub branch: Option<String>,
+    // Added after the ignore entries of the source configuration
+    pub ignore_list: Vec<PathPattern>,
+    // Names of environment variables, not the credentials themselves
+    pub pat_variable: Option<String>,
+    pub ssh_key_variable: Option<String>,
+    pub pat_username: Option<String>,
+    pub known_hosts: Option<String>,
 }
 
 pub(crate) struct DestinationConfiguration {
-    pub lock_list: Vec<String>,
+    pub lock_list: Vec<PathPattern>,
     pub path: Option<String>,
+    pub commit_message: Option<String>,
 }
 
 fn unwrap_array(
-    mut output: HashSet<String>,
+    mut output: Vec<PathPattern>,
     input: Vec<JsonValue>,
     key_name_on_error: &str,
     filename_on_error: &str,
-) -> Result<HashSet<String>, String> {
+) -> Result<Vec<PathPattern>, String> {
     for array_element in input.into_iter() {
         match array_element {
-            JsonValue::String(s) => match sanitize(s) {
+            JsonValue::String(s) => match parse_pattern(s) {
                 None => {},
-                Some(sanitized) => {
-                    output.insert(sanitized);
+                Some(pattern) => {
+                    output.push(pattern);
                 }
             },
-            JsonValue::Short(s) => match sanitize(String::from(s.as_str())) {
+            JsonValue::Short(s) => match parse_pattern(String::from(s.as_str())) {
                 None => {},
-                Some(sanitized) => {
-                    output.insert(sanitized);
+                Some(pattern) => {
+                    output.push(pattern);
                 }
             },
             _ => {
@@ -43,11 +75,11 @@ fn unwrap_array(
 }
 
 fn unwrap_object(
-    output: HashSet<String>,
+    output: Vec<PathPattern>,
     obj: &mut json::object::Object,
     key: &str,
     filename_on_error: &str,
-) -> Result<HashSet<String>, String> {
+) -> Result<Vec<PathPattern>, String> {
     match obj.remove(key) {
         None => Ok(output),
         Some(value_at_key) => match value_at_key {
@@ -59,6 +91,29 @@ fn unwrap_object(
     }
 }
 
+fn unwrap_optional_object(
+    obj: &mut json::object::Object,
+    key: &str,
+    filename_on_error: &str,
+) -> Result<Option<Vec<PathPattern>>, String> {
+    if obj.get(key).is_none() {
+        return Ok(None);
+    }
+    match unwrap_object(Vec::new(), obj, key, filename_on_error) {
+        Err(e) => Err(e),
+        Ok(list) => Ok(Some(list)),
+    }
+}
+
+// The implicit entries are placed after the entries from the configuration file
+// so that a negated pattern can never re-include them.
+fn append_implicit_entries(mut list: Vec<PathPattern>, config_file_name: &str) -> Vec<PathPattern> {
+    list.push(literal_pattern(".git"));
+    list.push(literal_pattern(".github"));
+    list.push(literal_pattern(config_file_name));
+    list
+}
+
 fn read_path_key(
     obj: &mut json::object::Object,
     filename_on_error: &str,
@@ -75,90 +130,375 @@ fn read_path_key(
     }
 }
 
+fn read_optional_string(
+    obj: &mut json::object::Object,
+    key: &str,
+    filename_on_error: &str,
+) -> Result<Option<String>, String> {
+    match obj.remove(key) {
+        None => Ok(None),
+        Some(value_at_key) => match value_at_key {
+            JsonValue::String(s) => Ok(Some(s)),
+            JsonValue::Short(s) => Ok(Some(String::from(s.as_str()))),
+            _ => Err(format!(
+                "Expected {key} element of {filename_on_error} to be a string"
+            )),
+        },
+    }
+}
+
+fn read_required_string(
+    obj: &mut json::object::Object,
+    key: &str,
+    element_on_error: &str,
+    filename_on_error: &str,
+) -> Result<String, String> {
+    match obj.remove(key) {
+        None => Err(format!(
+            "Expected {element_on_error} element of {filename_on_error} to contain {key}"
+        )),
+        Some(value_at_key) => match value_at_key {
+            JsonValue::String(s) => Ok(s),
+            JsonValue::Short(s) => Ok(String::from(s.as_str())),
+            _ => Err(format!(
+                "Expected {key} of {element_on_error} element of {filename_on_error} to be a string"
+            )),
+        },
+    }
+}
+
+fn unwrap_replace_rule(value: JsonValue, filename_on_error: &str) -> Result<ReplaceRule, String> {
+    let mut obj = match value {
+        JsonValue::Object(obj) => obj,
+        _ => {
+            return Err(format!(
+                "Expected sub-elements of replace element of {filename_on_error} to be objects"
+            ))
+        }
+    };
+    let files_string = match read_required_string(&mut obj, "files", "replace", filename_on_error) {
+        Err(e) => return Err(e),
+        Ok(s) => s,
+    };
+    let files = match parse_pattern(files_string) {
+        Some(pattern) if !pattern.negated => pattern,
+        _ => {
+            return Err(format!(
+                "Expected files of replace element of {filename_on_error} to be a non-negated path pattern"
+            ))
+        }
+    };
+    let pattern_string =
+        match read_required_string(&mut obj, "pattern", "replace", filename_on_error) {
+            Err(e) => return Err(e),
+            Ok(s) => s,
+        };
+    let pattern = match Regex::new(&pattern_string) {
+        Err(e) => {
+            return Err(format!(
+                "Invalid pattern in replace element of {filename_on_error}: {e}"
+            ))
+        }
+        Ok(r) => r,
+    };
+    let replacement =
+        match read_required_string(&mut obj, "replacement", "replace", filename_on_error) {
+            Err(e) => return Err(e),
+            Ok(s) => s,
+        };
+    Ok(ReplaceRule {
+        files: files,
+        pattern: pattern,
+        replacement: replacement,
+    })
+}
+
+fn read_replace_key(
+    obj: &mut json::object::Object,
+    filename_on_error: &str,
+) -> Result<Vec<ReplaceRule>, String> {
+    match obj.remove("replace") {
+        None => Ok(Vec::new()),
+        Some(value_at_key) => match value_at_key {
+            JsonValue::Array(array) => {
+                let mut rules: Vec<ReplaceRule> = Vec::new();
+                for array_element in array.into_iter() {
+                    match unwrap_replace_rule(array_element, filename_on_error) {
+                        Err(e) => return Err(e),
+                        Ok(rule) => rules.push(rule),
+                    }
+                }
+                Ok(rules)
+            }
+            _ => Err(format!(
+                "Expected replace element of {filename_on_error} to be an array"
+            )),
+        },
+    }
+}
+
+fn unwrap_secret_rule(value: JsonValue, filename_on_error: &str) -> Result<SecretRule, String> {
+    let mut obj = match value {
+        JsonValue::Object(obj) => obj,
+        _ => {
+            return Err(format!(
+            "Expected sub-elements of secrets.rules element of {filename_on_error} to be objects"
+        ))
+        }
+    };
+    let name = match read_required_string(&mut obj, "name", "secrets.rules", filename_on_error) {
+        Err(e) => return Err(e),
+        Ok(s) => s,
+    };
+    let pattern_string =
+        match read_required_string(&mut obj, "pattern", "secrets.rules", filename_on_error) {
+            Err(e) => return Err(e),
+            Ok(s) => s,
+        };
+    let pattern = match regex::bytes::Regex::new(&pattern_string) {
+        Err(e) => {
+            return Err(format!(
+                "Invalid pattern in secrets.rules element of {filename_on_error}: {e}"
+            ))
+        }
+        Ok(r) => r,
+    };
+    let min_entropy = match obj.remove("entropy") {
+        None => None,
+        Some(value_at_key) => match value_at_key.as_f64() {
+            None => {
+                return Err(format!(
+                "Expected entropy of secrets.rules element of {filename_on_error} to be a number"
+            ))
+            }
+            Some(n) => Some(n),
+        },
+    };
+    Ok(SecretRule {
+        name: name,
+        pattern: pattern,
+        min_entropy: min_entropy,
+    })
+}
+
+fn read_secrets_key(
+    obj: &mut json::object::Object,
+    filename_on_error: &str,
+) -> Result<SecretScanConfiguration, String> {
+    let mut configuration = SecretScanConfiguration {
+        rules: builtin_secret_rules(),
+        allow_list: HashSet::new(),
+    };
+    let mut secrets_obj = match obj.remove("secrets") {
+        None => return Ok(configuration),
+        Some(value_at_key) => match value_at_key {
+            JsonValue::Object(o) => o,
+            _ => {
+                return Err(format!(
+                    "Expected secrets element of {filename_on_error} to be an object"
+                ))
+            }
+        },
+    };
+    match secrets_obj.remove("rules") {
+        None => {}
+        Some(JsonValue::Array(array)) => {
+            for array_element in array.into_iter() {
+                match unwrap_secret_rule(array_element, filename_on_error) {
+                    Err(e) => return Err(e),
+                    Ok(rule) => configuration.rules.push(rule),
+                }
+            }
+        }
+        Some(_) => {
+            return Err(format!(
+                "Expected secrets.rules element of {filename_on_error} to be an array"
+            ))
+        }
+    }
+    match secrets_obj.remove("allow") {
+        None => {}
+        Some(JsonValue::Array(array)) => {
+            for array_element in array.into_iter() {
+                match array_element.as_str() {
+                    None => {
+                        return Err(format!(
+                            "Expected sub-elements of secrets.allow element of {filename_on_error} to be strings"
+                        ))
+                    }
+                    Some(fingerprint) => {
+                        configuration.allow_list.insert(String::from(fingerprint));
+                    }
+                }
+            }
+        }
+        Some(_) => {
+            return Err(format!(
+                "Expected secrets.allow element of {filename_on_error} to be an array"
+            ))
+        }
+    }
+    Ok(configuration)
+}
+
+fn read_authors_key(
+    obj: &mut json::object::Object,
+    filename_on_error: &str,
+) -> Result<HashMap<String, GitIdentity>, String> {
+    let mut authors: HashMap<String, GitIdentity> = HashMap::new();
+    let authors_obj = match obj.remove("authors") {
+        None => return Ok(authors),
+        Some(value_at_key) => match value_at_key {
+            JsonValue::Object(o) => o,
+            _ => {
+                return Err(format!(
+                    "Expected authors element of {filename_on_error} to be an object"
+                ))
+            }
+        },
+    };
+    for (source_email, value) in authors_obj.iter() {
+        let mut identity_obj = match value {
+            JsonValue::Object(o) => o.clone(),
+            _ => {
+                return Err(format!(
+                    "Expected sub-elements of authors element of {filename_on_error} to be objects"
+                ))
+            }
+        };
+        let name =
+            match read_required_string(&mut identity_obj, "name", "authors", filename_on_error) {
+                Err(e) => return Err(e),
+                Ok(s) => s,
+            };
+        let email =
+            match read_required_string(&mut identity_obj, "email", "authors", filename_on_error) {
+                Err(e) => return Err(e),
+                Ok(s) => s,
+            };
+        authors.insert(
+            source_email.to_lowercase(),
+            GitIdentity {
+                name: name,
+                email: email,
+            },
+        );
+    }
+    Ok(authors)
+}
+
 const SOURCE_CONFIG_FILE_NAME: &str = ".yellow-chameleon-source.json";
 
+// The manifest only matters at the root of the source repository,
+// but it is never published from any directory.
+fn append_implicit_source_entries(list: Vec<PathPattern>) -> Vec<PathPattern> {
+    let mut list = append_implicit_entries(list, SOURCE_CONFIG_FILE_NAME);
+    list.push(literal_pattern(MANIFEST_FILE_NAME));
+    list
+}
+
+// additional_ignore_list comes after the entries of the configuration file,
+// so that a destination can ignore paths that the configuration file re-includes.
 pub(crate) fn get_source_configuration(
     source_path: &String,
+    additional_ignore_list: Vec<PathPattern>,
 ) -> Result<SourceConfiguration, String> {
     let read_path = format!("{source_path}/{SOURCE_CONFIG_FILE_NAME}");
     let json_blob_option = match read_json_file(&read_path) {
         Err(e) => return Err(e),
         Ok(j) => j,
     };
-    let mut initial_ignore_set: HashSet<String> = HashSet::new();
-    initial_ignore_set.insert(String::from(".git"));
-    initial_ignore_set.insert(String::from(".github"));
-    initial_ignore_set.insert(String::from(SOURCE_CONFIG_FILE_NAME));
-    let final_ignore_set_result = match json_blob_option {
-        None => Ok(initial_ignore_set),
+    match json_blob_option {
+        None => Ok(SourceConfiguration {
+            ignore_list: append_implicit_source_entries(additional_ignore_list),
+            include_list: None,
+            replace_rules: Vec::new(),
+            secret_scan: SecretScanConfiguration {
+                rules: builtin_secret_rules(),
+                allow_list: HashSet::new(),
+            },
+            authors: HashMap::new(),
+        }),
         Some(json_blob) => match json_blob {
-            JsonValue::Object(mut obj) => unwrap_object(
-                initial_ignore_set,
-                &mut obj,
-                "ignore",
-                SOURCE_CONFIG_FILE_NAME,
-            ),
+            JsonValue::Object(mut obj) => {
+                let ignore_list =
+                    match unwrap_object(Vec::new(), &mut obj, "ignore", SOURCE_CONFIG_FILE_NAME) {
+                        Err(e) => return Err(e),
+                        Ok(mut list) => {
+                            list.extend(additional_ignore_list);
+                            append_implicit_source_entries(list)
+                        }
+                    };
+                let include_list =
+                    match unwrap_optional_object(&mut obj, "include", SOURCE_CONFIG_FILE_NAME) {
+                        Err(e) => return Err(e),
+                        Ok(l) => l,
+                    };
+                let replace_rules = match read_replace_key(&mut obj, SOURCE_CONFIG_FILE_NAME) {
+                    Err(e) => return Err(e),
+                    Ok(r) => r,
+                };
+                let secret_scan = match read_secrets_key(&mut obj, SOURCE_CONFIG_FILE_NAME) {
+                    Err(e) => return Err(e),
+                    Ok(s) => s,
+                };
+                let authors = match read_authors_key(&mut obj, SOURCE_CONFIG_FILE_NAME) {
+                    Err(e) => return Err(e),
+                    Ok(a) => a,
+                };
+
+                Ok(SourceConfiguration {
+                    ignore_list: ignore_list,
+                    include_list: include_list,
+                    replace_rules: replace_rules,
+                    secret_scan: secret_scan,
+                    authors: authors,
+                })
+            }
             _ => Err(format!(
                 "Expected top-level element of {SOURCE_CONFIG_FILE_NAME} to be an object"
             )),
         },
-    };
-    match final_ignore_set_result {
-        Err(e) => Err(e),
-        Ok(set) => {
-            let mut ignore_list: Vec<String> = set.into_iter().collect();
-            ignore_list.sort_unstable();
-            Ok(SourceConfiguration {
-                ignore_list: ignore_list,
-            })
-        }
     }
 }
 
 const DEST_CONFIG_FILE_NAME: &str = ".yellow-chameleon-destination.json";
 
-pub(crate) fn get_destination_configuration() -> Result<DestinationConfiguration, String> {
+pub(crate) fn get_destination_configuration(
+    destination_directory: &str,
+) -> Result<DestinationConfiguration, String> {
     let json_blob_option =
-        match read_json_file(format!("destination/{DEST_CONFIG_FILE_NAME}").as_str()) {
+        match read_json_file(format!("{destination_directory}/{DEST_CONFIG_FILE_NAME}").as_str()) {
             Err(e) => return Err(e),
             Ok(j) => j,
         };
-    let mut initial_lock_set: HashSet<String> = HashSet::new();
-    initial_lock_set.insert(String::from(".git"));
-    initial_lock_set.insert(String::from(".github"));
-    initial_lock_set.insert(String::from(DEST_CONFIG_FILE_NAME));
     match json_blob_option {
-        None => {
-            let mut list: Vec<String> = initial_lock_set.into_iter().collect();
-            list.sort_unstable();
-            Ok(DestinationConfiguration {
-                lock_list: list,
-                path: None,
-            })
-        }
+        None => Ok(DestinationConfiguration {
+            lock_list: append_implicit_entries(Vec::new(), DEST_CONFIG_FILE_NAME),
+            path: None,
+            commit_message: None,
+        }),
         Some(json_blob) => match json_blob {
             JsonValue::Object(mut obj) => {
-                let final_lock_list = match unwrap_object(
-                    initial_lock_set,
-                    &mut obj,
-                    "lock",
-                    DEST_CONFIG_FILE_NAME,
-                ) {
-                    Err(e) => return Err(e),
-                    Ok(set) => {
-                        let mut list: Vec<String> = set.into_iter().collect();
-                        list.sort_unstable();
-                        list
-                    }
-                };
+                let final_lock_list =
+                    match unwrap_object(Vec::new(), &mut obj, "lock", DEST_CONFIG_FILE_NAME) {
+                        Err(e) => return Err(e),
+                        Ok(list) => append_implicit_entries(list, DEST_CONFIG_FILE_NAME),
+                    };
                 let path = match read_path_key(&mut obj, DEST_CONFIG_FILE_NAME) {
                     Err(e) => return Err(e),
                     Ok(p) => p,
                 };
+                let commit_message =
+                    match read_optional_string(&mut obj, "commit_message", DEST_CONFIG_FILE_NAME) {
+                        Err(e) => return Err(e),
+                        Ok(m) => m,
+                    };
 
                 Ok(DestinationConfiguration {
                     lock_list: final_lock_list,
                     path: path,
+                    commit_message: commit_message,
                 })
             }
             _ => Err(format!(
@@ -167,3 +507,114 @@ pub(crate) fn get_destination_configuration() -> Result<DestinationConfiguration
         },
     }
 }
+
+pub(crate) const MANIFEST_FILE_NAME: &str = ".yellow-chameleon.json";
+
+fn unwrap_manifest_destination(value: JsonValue) -> Result<ManifestDestination, String> {
+    let mut obj = match value {
+        JsonValue::Object(obj) => obj,
+        _ => {
+            return Err(format!(
+            "Expected sub-elements of destinations element of {MANIFEST_FILE_NAME} to be objects"
+        ))
+        }
+    };
+    let repository =
+        match read_required_string(&mut obj, "repository", "destinations", MANIFEST_FILE_NAME) {
+            Err(e) => return Err(e),
+            Ok(s) => s,
+        };
+    let name = match read_optional_string(&mut obj, "name", MANIFEST_FILE_NAME) {
+        Err(e) => return Err(e),
+        Ok(n) => n.unwrap_or_else(|| repository.clone()),
+    };
+    let ignore_list = match unwrap_object(Vec::new(), &mut obj, "ignore", MANIFEST_FILE_NAME) {
+        Err(e) => return Err(e),
+        Ok(list) => list,
+    };
+    let host = match read_optional_string(&mut obj, "host", MANIFEST_FILE_NAME) {
+        Err(e) => return Err(e),
+        Ok(s) => s,
+    };
+    let source_path = match read_optional_string(&mut obj, "source_path", MANIFEST_FILE_NAME) {
+        Err(e) => return Err(e),
+        Ok(s) => s,
+    };
+    let branch = match read_optional_string(&mut obj, "branch", MANIFEST_FILE_NAME) {
+        Err(e) => return Err(e),
+        Ok(s) => s,
+    };
+    let pat_variable = match read_optional_string(&mut obj, "pat_variable", MANIFEST_FILE_NAME) {
+        Err(e) => return Err(e),
+        Ok(s) => s,
+    };
+    let ssh_key_variable =
+        match read_optional_string(&mut obj, "ssh_key_variable", MANIFEST_FILE_NAME) {
+            Err(e) => return Err(e),
+            Ok(s) => s,
+        };
+    let pat_username = match read_optional_string(&mut obj, "pat_username", MANIFEST_FILE_NAME) {
+        Err(e) => return Err(e),
+        Ok(s) => s,
+    };
+    let known_hosts = match read_optional_string(&mut obj, "known_hosts", MANIFEST_FILE_NAME) {
+        Err(e) => return Err(e),
+        Ok(s) => s,
+    };
+    Ok(ManifestDestination {
+        name: name,
+        repository: repository,
+        host: host,
+        source_path: match source_path {
+            None => None,
+            Some(p) => sanitize(p),
+        },
+        branch: branch,
+        ignore_list: ignore_list,
+        pat_variable: pat_variable,
+        ssh_key_variable: ssh_key_variable,
+        pat_username: pat_username,
+        known_hosts: known_hosts,
+    })
+}
+
+// Returns None if the source repository has no manifest.
+pub(crate) fn get_manifest(
+    source_repository_path: &str,
+) -> Result<Option<Vec<ManifestDestination>>, String> {
+    let json_blob_option =
+        match read_json_file(format!("{source_repository_path}/{MANIFEST_FILE_NAME}").as_str()) {
+            Err(e) => return Err(e),
+            Ok(j) => j,
+        };
+    let mut obj = match json_blob_option {
+        None => return Ok(None),
+        Some(JsonValue::Object(obj)) => obj,
+        Some(_) => {
+            return Err(format!(
+                "Expected top-level element of {MANIFEST_FILE_NAME} to be an object"
+            ))
+        }
+    };
+    let array = match obj.remove("destinations") {
+        Some(JsonValue::Array(array)) => array,
+        _ => {
+            return Err(format!(
+                "Expected {MANIFEST_FILE_NAME} to contain a destinations array"
+            ))
+        }
+    };
+    if array.is_empty() {
+        return Err(format!(
+            "Expected destinations element of {MANIFEST_FILE_NAME} to contain at least one destination"
+        ));
+    }
+    let mut destinations: Vec<ManifestDestination> = Vec::new();
+    for array_element in array.into_iter() {
+        match unwrap_manifest_destination(array_element) {
+            Err(e) => return Err(e),
+            Ok(d) => destinations.push(d),
+        }
+    }
+    Ok(Some(destinations))
+}
@@ -0,0 +1,167 @@
+use crate::commands::{
+    git_add_all, git_checkout_branch, git_clone, git_commit, git_current_branch, git_diff,
+    git_diff_since, git_export_commit, git_head, git_is_ancestor, git_last_synced_commit,
+    git_list_commits, git_push, git_same_tree, GitDiffResult, GitIdentity, SourceCommit,
+};
+use crate::get_environment_configuration::EnvironmentConfiguration;
+use crate::sync_error::SyncError;
+
+// Every git operation of a sync goes through this trait, so that the git CLI
+// and the native backend are interchangeable.
+pub(crate) trait GitBackend {
+    fn clone_destination(&self, repo_url: &str, directory: &str) -> Result<(), SyncError>;
+    // Checks out branch, creating it from the currently checked out branch
+    // if it does not exist in the remote repository yet.
+    fn checkout_branch(&self, cwd: &str, branch: &str) -> Result<(), String>;
+    fn add_all(&self, cwd: &str) -> Result<(), String>;
+    // Compares the index to HEAD, so add_all should be called first.
+    fn diff(&self, cwd: &str) -> Result<GitDiffResult, String>;
+    // Compares HEAD to an earlier commit.
+    fn diff_since(&self, cwd: &str, revision: &str) -> Result<GitDiffResult, String>;
+    fn commit(
+        &self,
+        cwd: &str,
+        commit_message: &str,
+        author: &GitIdentity,
+        committer: &GitIdentity,
+    ) -> Result<(), String>;
+    fn push(&self, cwd: &str, repo_url: &str, branch: &str, force: bool) -> Result<(), SyncError>;
+    fn head(&self, cwd: &str) -> Result<String, String>;
+    fn last_synced_commit(&self, cwd: &str) -> Result<Option<String>, String>;
+    fn is_ancestor(&self, cwd: &str, ancestor: &str, descendant: &str) -> bool;
+    // Returns false if either revision does not exist.
+    fn same_tree(&self, cwd: &str, revision: &str, other_revision: &str) -> bool;
+    // Lists the commits on the first-parent line of HEAD after since (or only HEAD
+    // if since is None), oldest first.
+    fn list_commits(&self, cwd: &str, since: Option<&str>) -> Result<Vec<SourceCommit>, String>;
+    fn current_branch(&self, cwd: &str) -> Option<String>;
+    // Writes the tree of a commit to output_directory without touching
+    // the index or working tree of the repository.
+    fn export_commit(
+        &self,
+        repository: &str,
+        sha: &str,
+        output_directory: &str,
+    ) -> Result<(), String>;
+
+    // Returns None if cwd is not a git repository with at least one commit.
+    fn head_commit(&self, cwd: &str) -> Option<SourceCommit> {
+        match self.list_commits(cwd, None) {
+            Err(_) => None,
+            Ok(commits) => commits.into_iter().next(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum GitBackendKind {
+    Cli,
+    Native,
+}
+
+pub(crate) fn parse_git_backend_kind(value: &str) -> Option<GitBackendKind> {
+    match value {
+        "cli" => Some(GitBackendKind::Cli),
+        "native" => Some(GitBackendKind::Native),
+        _ => None,
+    }
+}
+
+// Runs the git binary found on PATH.
+pub(crate) struct CliGitBackend;
+
+impl GitBackend for CliGitBackend {
+    fn clone_destination(&self, repo_url: &str, directory: &str) -> Result<(), SyncError> {
+        git_clone(repo_url, directory)
+    }
+
+    fn checkout_branch(&self, cwd: &str, branch: &str) -> Result<(), String> {
+        git_checkout_branch(cwd, branch)
+    }
+
+    fn add_all(&self, cwd: &str) -> Result<(), String> {
+        git_add_all(cwd)
+    }
+
+    fn diff(&self, cwd: &str) -> Result<GitDiffResult, String> {
+        git_diff(cwd)
+    }
+
+    fn diff_since(&self, cwd: &str, revision: &str) -> Result<GitDiffResult, String> {
+        git_diff_since(cwd, revision)
+    }
+
+    fn commit(
+        &self,
+        cwd: &str,
+        commit_message: &str,
+        author: &GitIdentity,
+        committer: &GitIdentity,
+    ) -> Result<(), String> {
+        git_commit(cwd, commit_message, author, committer)
+    }
+
+    fn push(&self, cwd: &str, repo_url: &str, branch: &str, force: bool) -> Result<(), SyncError> {
+        git_push(cwd, repo_url, branch, force)
+    }
+
+    fn head(&self, cwd: &str) -> Result<String, String> {
+        git_head(cwd)
+    }
+
+    fn last_synced_commit(&self, cwd: &str) -> Result<Option<String>, String> {
+        git_last_synced_commit(cwd)
+    }
+
+    fn is_ancestor(&self, cwd: &str, ancestor: &str, descendant: &str) -> bool {
+        git_is_ancestor(cwd, ancestor, descendant)
+    }
+
+    fn same_tree(&self, cwd: &str, revision: &str, other_revision: &str) -> bool {
+        git_same_tree(cwd, revision, other_revision)
+    }
+
+    fn list_commits(&self, cwd: &str, since: Option<&str>) -> Result<Vec<SourceCommit>, String> {
+        git_list_commits(cwd, since)
+    }
+
+    fn current_branch(&self, cwd: &str) -> Option<String> {
+        git_current_branch(cwd)
+    }
+
+    fn export_commit(
+        &self,
+        repository: &str,
+        sha: &str,
+        output_directory: &str,
+    ) -> Result<(), String> {
+        git_export_commit(repository, sha, output_directory)
+    }
+}
+
+#[cfg(feature = "native-git")]
+pub(crate) fn get_git_backend(
+    environment_configuration: &EnvironmentConfiguration,
+) -> Result<Box<dyn GitBackend>, String> {
+    match environment_configuration.git_backend {
+        GitBackendKind::Cli => Ok(Box::new(CliGitBackend)),
+        GitBackendKind::Native => {
+            match crate::native_git_backend::NativeGitBackend::new(environment_configuration) {
+                Err(e) => Err(e),
+                Ok(b) => Ok(Box::new(b)),
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "native-git"))]
+pub(crate) fn get_git_backend(
+    environment_configuration: &EnvironmentConfiguration,
+) -> Result<Box<dyn GitBackend>, String> {
+    match environment_configuration.git_backend {
+        GitBackendKind::Cli => Ok(Box::new(CliGitBackend)),
+        GitBackendKind::Native => Err(String::from(
+            "This build of yellow-chameleon does not include the native git backend",
+        )),
+    }
+}
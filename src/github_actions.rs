@@ -0,0 +1,203 @@
+use std::env::var;
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use json::JsonValue;
+
+use crate::output::mask_secrets;
+
+// Appends text to the file named by an environment variable, if it is set.
+// The GitHub Actions runner sets GITHUB_OUTPUT and GITHUB_STEP_SUMMARY.
+fn append_to_file_in(variable_name: &str, text: &str) -> Result<(), String> {
+    let path = match var(variable_name) {
+        Err(_) => return Ok(()),
+        Ok(p) => p,
+    };
+    if path.is_empty() {
+        return Ok(());
+    }
+    let mut file = match OpenOptions::new().append(true).create(true).open(&path) {
+        Err(e) => return Err(format!("Unable to open {variable_name} file {path}: {e}")),
+        Ok(f) => f,
+    };
+    match file.write_all(mask_secrets(text).as_bytes()) {
+        Err(e) => Err(format!("Unable to write {variable_name} file {path}: {e}")),
+        Ok(_) => Ok(()),
+    }
+}
+
+fn string_or_empty(value: &JsonValue) -> &str {
+    value.as_str().unwrap_or("")
+}
+
+// Joins the non-empty values of a string element of every report with spaces.
+fn join_reports(reports: &[JsonValue], value: impl Fn(&JsonValue) -> &JsonValue) -> String {
+    let values: Vec<&str> = reports
+        .iter()
+        .map(|report| string_or_empty(value(report)))
+        .filter(|v| !v.is_empty())
+        .collect();
+    values.join(" ")
+}
+
+// Only a sync that pushed a commit counts as changed, so a dry run never does.
+// When a manifest is used, the outputs combine every destination.
+fn build_outputs(reports: &[JsonValue]) -> String {
+    let changed = reports.iter().any(|report| report["result"] == "changes");
+    let files_changed: usize = reports.iter().map(|report| report["changes"].len()).sum();
+    // Outputs are single line, and none of these values can contain a newline.
+    format!(
+        "changed={changed}\ncommit-sha={}\nfiles-changed={files_changed}\ndestination-url={}\n",
+        join_reports(reports, |report| &report["commit_sha"]),
+        join_reports(reports, |report| &report["configuration"]
+            ["destination_url"]),
+    )
+}
+
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        if "\\`*_{}[]<>()#|!~".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn optional_count(value: &JsonValue) -> String {
+    match value.as_u64() {
+        None => String::from("binary"),
+        Some(c) => c.to_string(),
+    }
+}
+
+fn build_entries_table(
+    summary: &mut String,
+    title: &str,
+    entries: &JsonValue,
+    matched: &JsonValue,
+) {
+    if entries.is_empty() {
+        return;
+    }
+    summary.push_str(&format!(
+        "\n#### {title}\n\n| Entry | Matched |\n| --- | --- |\n"
+    ));
+    for entry in entries.members() {
+        let is_matched = matched.members().any(|m| m == entry);
+        summary.push_str(&format!(
+            "| {} | {} |\n",
+            escape_markdown(string_or_empty(entry)),
+            if is_matched { "yes" } else { "no" }
+        ));
+    }
+}
+
+fn build_step_summary(report: &JsonValue) -> String {
+    let destination = escape_markdown(string_or_empty(&report["configuration"]["destination_url"]));
+    let mut summary = match report["name"].as_str() {
+        None => String::from("### yellow-chameleon\n\n"),
+        Some(name) => format!("### yellow-chameleon: {}\n\n", escape_markdown(name)),
+    };
+    let file_count = report["changes"].len();
+    if report["result"] == "changes" {
+        summary.push_str(&format!(
+            "Pushed {file_count} changed files to {destination} in commit `{}`.\n",
+            string_or_empty(&report["commit_sha"])
+        ));
+    } else if report["result"] == "dry_run" {
+        summary.push_str(&format!(
+            "Dry run: {file_count} files would change in {destination}.\n"
+        ));
+    } else if report["result"] == "no_changes" {
+        summary.push_str(&format!(
+            "No changes detected in source. {destination} was not updated.\n"
+        ));
+    } else {
+        summary.push_str(&format!(
+            "The sync to {destination} stopped due to an error: {}\n",
+            escape_markdown(string_or_empty(&report["error"]["message"]))
+        ));
+    }
+    if file_count > 0 {
+        summary.push_str(
+            "\n| Change | Path | Lines added | Lines deleted |\n| --- | --- | --- | --- |\n",
+        );
+        for change in report["changes"].members() {
+            let path = match change["previous_path"].as_str() {
+                None => escape_markdown(string_or_empty(&change["path"])),
+                Some(previous_path) => format!(
+                    "{} → {}",
+                    escape_markdown(previous_path),
+                    escape_markdown(string_or_empty(&change["path"]))
+                ),
+            };
+            summary.push_str(&format!(
+                "| {} | {path} | {} | {} |\n",
+                string_or_empty(&change["kind"]),
+                optional_count(&change["lines_added"]),
+                optional_count(&change["lines_deleted"]),
+            ));
+        }
+    }
+    build_entries_table(
+        &mut summary,
+        "Ignore entries",
+        &report["ignore_entries"],
+        &report["ignore_entries_matched"],
+    );
+    build_entries_table(
+        &mut summary,
+        "Lock entries",
+        &report["lock_entries"],
+        &report["lock_entries_matched"],
+    );
+    // Separates the summaries of piggybacked runs.
+    summary.push('\n');
+    summary
+}
+
+fn describe_result(report: &JsonValue) -> String {
+    let file_count = report["changes"].len();
+    if report["result"] == "changes" {
+        format!("{file_count} files changed")
+    } else if report["result"] == "dry_run" {
+        format!("dry run, {file_count} files would change")
+    } else if report["result"] == "no_changes" {
+        String::from("no changes")
+    } else {
+        format!("failed with exit code {}", report["error"]["exit_code"])
+    }
+}
+
+fn build_overview(reports: &[JsonValue]) -> String {
+    let mut overview = String::from(
+        "### yellow-chameleon destinations\n\n| Destination | Result |\n| --- | --- |\n",
+    );
+    for report in reports.iter() {
+        overview.push_str(&format!(
+            "| {} | {} |\n",
+            escape_markdown(string_or_empty(&report["name"])),
+            describe_result(report)
+        ));
+    }
+    overview.push('\n');
+    overview
+}
+
+pub(crate) fn write_github_actions_results(reports: &[JsonValue]) -> Result<(), String> {
+    match append_to_file_in("GITHUB_OUTPUT", &build_outputs(reports)) {
+        Err(e) => return Err(e),
+        Ok(_) => {}
+    }
+    let mut summary = if reports.len() > 1 {
+        build_overview(reports)
+    } else {
+        String::new()
+    };
+    for report in reports.iter() {
+        summary.push_str(&build_step_summary(report));
+    }
+    append_to_file_in("GITHUB_STEP_SUMMARY", &summary)
+}
@@ -0,0 +1,262 @@
+use json::{object, parse, JsonValue};
+use ureq::{Agent, Request};
+
+fn authorize(request: Request, token: &str) -> Request {
+    request
+        .set("Accept", "application/vnd.github+json")
+        .set("Content-Type", "application/json")
+        .set("Authorization", &format!("Bearer {token}"))
+        .set("X-GitHub-Api-Version", "2022-11-28")
+        .set("User-Agent", "yellow-chameleon")
+}
+
+fn read_response(
+    result: Result<ureq::Response, ureq::Error>,
+    action_on_error: &str,
+) -> Result<JsonValue, String> {
+    let response = match result {
+        Err(ureq::Error::Status(code, _)) => {
+            return Err(format!(
+                "Unable to {action_on_error}: the GitHub API returned status {code}"
+            ))
+        }
+        Err(ureq::Error::Transport(_)) => {
+            return Err(format!(
+                "Unable to {action_on_error}: could not connect to the GitHub API"
+            ))
+        }
+        Ok(r) => r,
+    };
+    let body = match response.into_string() {
+        Err(_) => {
+            return Err(format!(
+                "Unable to {action_on_error}: could not read the GitHub API response"
+            ))
+        }
+        Ok(b) => b,
+    };
+    match parse(&body) {
+        Err(_) => Err(format!(
+            "Unable to {action_on_error}: the GitHub API returned invalid JSON"
+        )),
+        Ok(j) => Ok(j),
+    }
+}
+
+fn read_html_url(pull_request: &JsonValue) -> String {
+    match pull_request["html_url"].as_str() {
+        None => String::new(),
+        Some(url) => String::from(url),
+    }
+}
+
+// Opens a pull request from head_branch into base_branch, or updates the title
+// and body of the one that is already open.
+// repository is written as owner/name. Returns the URL of the pull request.
+pub(crate) fn create_or_update_pull_request(
+    api_url: &str,
+    token: &str,
+    repository: &str,
+    head_branch: &str,
+    base_branch: &str,
+    title: &str,
+    body: &str,
+) -> Result<String, String> {
+    let owner = match repository.split_once('/') {
+        None => return Err(String::from(
+            "Expected destination-repository to be written as owner/name to open a pull request",
+        )),
+        Some((o, _)) => o,
+    };
+    let api_url = api_url.trim_end_matches('/');
+    let pulls_url = format!("{api_url}/repos/{repository}/pulls");
+    let agent = Agent::new();
+
+    let open_pull_requests = match read_response(
+        authorize(agent.get(&pulls_url), token)
+            .query("state", "open")
+            .query("head", &format!("{owner}:{head_branch}"))
+            .query("base", base_branch)
+            .call(),
+        "list pull requests",
+    ) {
+        Err(e) => return Err(e),
+        Ok(j) => j,
+    };
+
+    let existing_number = if open_pull_requests.is_array() && !open_pull_requests.is_empty() {
+        open_pull_requests[0]["number"].as_u64()
+    } else {
+        None
+    };
+
+    match existing_number {
+        Some(number) => {
+            match read_response(
+                authorize(agent.patch(&format!("{pulls_url}/{number}")), token).send_string(
+                    &object! {
+                        title: title,
+                        body: body,
+                    }
+                    .dump(),
+                ),
+                "update pull request",
+            ) {
+                Err(e) => Err(e),
+                Ok(pull_request) => Ok(read_html_url(&pull_request)),
+            }
+        }
+        None => {
+            match read_response(
+                authorize(agent.post(&pulls_url), token).send_string(
+                    &object! {
+                        title: title,
+                        head: head_branch,
+                        base: base_branch,
+                        body: body,
+                    }
+                    .dump(),
+                ),
+                "create pull request",
+            ) {
+                Err(e) => Err(e),
+                Ok(pull_request) => Ok(read_html_url(&pull_request)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    struct RecordedRequest {
+        request_line: String,
+        authorization: String,
+        body: String,
+    }
+
+    // Answers one request per response, in order, then returns what was received.
+    fn serve(responses: Vec<&'static str>) -> (String, thread::JoinHandle<Vec<RecordedRequest>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut content_length = 0;
+                let mut authorization = String::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    let (name, value) = line.split_once(": ").unwrap();
+                    match name.to_ascii_lowercase().as_str() {
+                        "content-length" => content_length = value.parse().unwrap(),
+                        "authorization" => authorization = String::from(value),
+                        _ => {}
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                requests.push(RecordedRequest {
+                    request_line: String::from(request_line.trim_end()),
+                    authorization: authorization,
+                    body: String::from_utf8(body).unwrap(),
+                });
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{response}",
+                    response.len()
+                )
+                .unwrap();
+            }
+            requests
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn creates_pull_request_when_none_is_open() {
+        let (url, server) = serve(vec![
+            "[]",
+            r#"{"number": 7, "html_url": "https://github.com/owner/name/pull/7"}"#,
+        ]);
+        let result = create_or_update_pull_request(
+            &format!("{url}/"),
+            "token",
+            "owner/name",
+            "sync",
+            "main",
+            "Sync",
+            "Changed files",
+        );
+        assert_eq!(result.unwrap(), "https://github.com/owner/name/pull/7");
+        let requests = server.join().unwrap();
+        assert_eq!(
+            requests[0].request_line,
+            "GET /repos/owner/name/pulls?state=open&head=owner%3Async&base=main HTTP/1.1"
+        );
+        assert_eq!(requests[0].authorization, "Bearer token");
+        assert_eq!(
+            requests[1].request_line,
+            "POST /repos/owner/name/pulls HTTP/1.1"
+        );
+        let body = parse(&requests[1].body).unwrap();
+        assert_eq!(body["head"], "sync");
+        assert_eq!(body["base"], "main");
+        assert_eq!(body["title"], "Sync");
+        assert_eq!(body["body"], "Changed files");
+    }
+
+    #[test]
+    fn updates_pull_request_that_is_already_open() {
+        let (url, server) = serve(vec![
+            r#"[{"number": 3, "html_url": "https://github.com/owner/name/pull/3"}]"#,
+            r#"{"number": 3, "html_url": "https://github.com/owner/name/pull/3"}"#,
+        ]);
+        let result = create_or_update_pull_request(
+            &url,
+            "token",
+            "owner/name",
+            "sync",
+            "main",
+            "New title",
+            "New body",
+        );
+        assert_eq!(result.unwrap(), "https://github.com/owner/name/pull/3");
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(
+            requests[1].request_line,
+            "PATCH /repos/owner/name/pulls/3 HTTP/1.1"
+        );
+        let body = parse(&requests[1].body).unwrap();
+        assert_eq!(body["title"], "New title");
+        assert_eq!(body["body"], "New body");
+        assert!(body["head"].is_null());
+    }
+
+    #[test]
+    fn repository_must_have_an_owner() {
+        assert!(create_or_update_pull_request(
+            "http://127.0.0.1:9",
+            "token",
+            "name",
+            "sync",
+            "main",
+            "Sync",
+            ""
+        )
+        .is_err());
+    }
+}
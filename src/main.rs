@@ -1,103 +1,478 @@
+#![allow(
+    clippy::len_zero,
+    clippy::needless_borrow,
+    clippy::needless_return,
+    clippy::question_mark,
+    clippy::redundant_field_names,
+    clippy::single_match
+)]
+
+mod apply_replace_rules;
+mod askpass;
 mod commands;
+mod commit_message;
+mod describe_changes;
 mod get_environment_configuration;
 mod get_json_configuration;
+mod git_backend;
+mod github_actions;
+mod github_api;
+mod mirror_history;
+#[cfg(feature = "native-git")]
+mod native_git_backend;
+#[cfg(feature = "native-git")]
+mod native_git_push;
+mod output;
+mod path_pattern;
 mod read_json_file;
+mod read_text_file;
+mod redact_private_regions;
+mod run_report;
 mod sanitize_path;
+mod scan_for_secrets;
+mod sync_error;
 mod transformations;
+mod write_ssh_key;
 
 use std::path::Path;
 use std::process::ExitCode;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
 
-use crate::commands::{git_clone, git_config, GitDiffResult};
-use crate::get_environment_configuration::get_environment_configuration;
-use crate::get_json_configuration::{get_destination_configuration, get_source_configuration};
-use crate::transformations::{apply_dest_path, apply_ignore_list, apply_lock_list, git_upload};
+use crate::askpass::answer_askpass_prompt;
+use json::JsonValue;
 
-fn main_impl() -> Result<GitDiffResult, String> {
-    let environment_configuration = match get_environment_configuration() {
-        Err(e) => return Err(e),
-        Ok(c) => c,
-    };
+use crate::commands::{mkdir_all, rm, set_git_credentials, GitDiffResult};
+use crate::commit_message::select_commit_message_template;
+use crate::describe_changes::describe_changes;
+use crate::get_environment_configuration::{
+    get_environment_configuration, get_manifest_destination_configuration,
+    get_shared_configuration, git_credentials, has_destination_input, in_work_path,
+    EnvironmentConfiguration, DEFAULT_PULL_REQUEST_BRANCH, SOURCE_REPOSITORY_PATH, WORK_PATH,
+};
+use crate::get_json_configuration::{
+    get_manifest, get_source_configuration, ManifestDestination, MANIFEST_FILE_NAME,
+};
+use crate::git_backend::get_git_backend;
+use crate::github_actions::write_github_actions_results;
+use crate::github_api::create_or_update_pull_request;
+use crate::mirror_history::mirror_history;
+use crate::output::{print_error, print_line, set_output_prefix};
+use crate::path_pattern::PathPattern;
+use crate::run_report::{
+    report_configuration, report_destination_name, report_outcome, report_phase,
+    report_pull_request, take_report, write_report,
+};
+use crate::sync_error::SyncError;
+use crate::transformations::{
+    apply_destination_transformations, apply_source_transformations, git_upload,
+};
+use crate::write_ssh_key::write_ssh_key;
+
+fn sync_destination(
+    mut environment_configuration: EnvironmentConfiguration,
+    additional_ignore_list: Vec<PathPattern>,
+) -> Result<(GitDiffResult, bool), SyncError> {
+    let started = Instant::now();
+    report_configuration(&environment_configuration);
 
     if !(Path::new(&environment_configuration.source_path).is_dir()) {
-        return Err(String::from("source path is not a directory"));
+        return Err(SyncError::SourceMissing(String::from(
+            "source path is not a directory",
+        )));
     }
 
-    let source_configuration =
-        match get_source_configuration(&environment_configuration.source_path) {
-            Err(e) => return Err(e),
-            Ok(c) => c,
-        };
-
-    match apply_ignore_list(
+    let source_configuration = match get_source_configuration(
         &environment_configuration.source_path,
-        source_configuration.ignore_list,
+        additional_ignore_list,
     ) {
-        Err(e) => return Err(e),
-        Ok(_) => {}
+        Err(e) => return Err(SyncError::Configuration(e)),
+        Ok(c) => c,
+    };
+
+    let git = match get_git_backend(&environment_configuration) {
+        Err(e) => return Err(SyncError::Configuration(e)),
+        Ok(b) => b,
+    };
+    report_phase("configuration", started);
+
+    // Fills in the source placeholders of the commit message.
+    let source_commit = git.head_commit(SOURCE_REPOSITORY_PATH);
+    if environment_configuration.source_ref.is_none() {
+        environment_configuration.source_ref = git.current_branch(SOURCE_REPOSITORY_PATH);
     }
 
-    match git_config(
-        &environment_configuration.git_name,
-        &environment_configuration.git_email,
-    ) {
-        Err(e) => return Err(e),
+    // The outgoing tree is built here, so that the source is never modified.
+    let staging_path = in_work_path(&environment_configuration.work_path, "staging");
+
+    if !environment_configuration.preserve_history {
+        let started = Instant::now();
+        match apply_source_transformations(
+            &environment_configuration.source_path,
+            &staging_path,
+            &source_configuration,
+        ) {
+            Err(e) => return Err(SyncError::Transformation(e)),
+            Ok(_) => {}
+        }
+        report_phase("source_transformations", started);
+    }
+
+    // Kept alive until the end of the run, then the key files are deleted.
+    let ssh_key_files = match &environment_configuration.dest_ssh_key {
+        None => None,
+        Some(key) => {
+            match write_ssh_key(key, environment_configuration.dest_known_hosts.as_deref()) {
+                Err(e) => return Err(SyncError::Internal(e)),
+                Ok(f) => Some(f),
+            }
+        }
+    };
+    environment_configuration.git_ssh_command =
+        ssh_key_files.as_ref().map(|f| f.ssh_command.clone());
+
+    match set_git_credentials(&git_credentials(&environment_configuration)) {
+        Err(e) => return Err(SyncError::Internal(e)),
         Ok(_) => {}
     }
 
-    match git_clone(
+    let destination_directory = in_work_path(&environment_configuration.work_path, "destination");
+    let started = Instant::now();
+    match git.clone_destination(
         &environment_configuration.dest_repo_url,
-        &environment_configuration.dest_pat,
+        &destination_directory,
     ) {
         Err(e) => return Err(e),
         Ok(_) => {}
     }
+    report_phase("clone", started);
 
-    let destination_configuration = match get_destination_configuration() {
+    let default_branch = match git.current_branch(&destination_directory) {
+        None => {
+            return Err(SyncError::Clone(String::from(
+                "Unable to determine the default branch of the destination repository",
+            )))
+        }
+        Some(b) => b,
+    };
+
+    // In pull request mode, the sync branch is always rebuilt on top of
+    // the default branch and force pushed.
+    let dest_branch = match &environment_configuration.dest_branch {
+        None => {
+            if environment_configuration.pull_request {
+                String::from(DEFAULT_PULL_REQUEST_BRANCH)
+            } else {
+                default_branch.clone()
+            }
+        }
+        Some(b) => {
+            if !environment_configuration.pull_request {
+                match git.checkout_branch(&destination_directory, b) {
+                    Err(e) => return Err(SyncError::Clone(e)),
+                    Ok(_) => {}
+                }
+            }
+            b.clone()
+        }
+    };
+
+    let git_diff_result = if environment_configuration.preserve_history {
+        let started = Instant::now();
+        let result = mirror_history(
+            git.as_ref(),
+            &environment_configuration,
+            &source_configuration,
+            &dest_branch,
+        );
+        report_phase("history", started);
+        result
+    } else {
+        let started = Instant::now();
+        let destination_configuration = match apply_destination_transformations(
+            &staging_path,
+            &environment_configuration.work_path,
+        ) {
+            Err(e) => return Err(SyncError::Transformation(e)),
+            Ok(c) => c,
+        };
+        report_phase("destination_transformations", started);
+        let started = Instant::now();
+        let result = git_upload(
+            git.as_ref(),
+            &staging_path,
+            &environment_configuration,
+            &source_configuration.secret_scan,
+            select_commit_message_template(
+                &environment_configuration.commit_message,
+                &destination_configuration.commit_message,
+            ),
+            source_commit.as_ref(),
+            &dest_branch,
+        );
+        report_phase("upload", started);
+        result
+    };
+
+    let git_diff_result = match git_diff_result {
         Err(e) => return Err(e),
+        Ok(r) => r,
+    };
+
+    if environment_configuration.pull_request && !environment_configuration.dry_run {
+        if let GitDiffResult::Changes(changes) = &git_diff_result {
+            let started = Instant::now();
+            // Checked when the configuration is read.
+            let dest_pat = match &environment_configuration.dest_pat {
+                None => {
+                    return Err(SyncError::Internal(String::from(
+                        "Internal Error: destination-pat is not defined",
+                    )))
+                }
+                Some(p) => p,
+            };
+            match create_or_update_pull_request(
+                &environment_configuration.api_url,
+                dest_pat,
+                &environment_configuration.dest_repo,
+                &dest_branch,
+                &default_branch,
+                &environment_configuration.pull_request_title,
+                &format!(
+                    "This pull request was created by yellow-chameleon.\n\n```\n{}\n```",
+                    describe_changes(changes)
+                ),
+            ) {
+                Err(e) => return Err(SyncError::PullRequest(e)),
+                Ok(url) => {
+                    print_line(&format!("Pull request: {url}"));
+                    report_pull_request(&url);
+                }
+            }
+            report_phase("pull_request", started);
+        }
+    }
+
+    Ok((git_diff_result, environment_configuration.dry_run))
+}
+
+fn main_impl() -> Result<(GitDiffResult, bool), SyncError> {
+    let environment_configuration = match get_environment_configuration() {
+        Err(e) => return Err(SyncError::Configuration(e)),
         Ok(c) => c,
     };
+    sync_in_work_path(environment_configuration, Vec::new())
+}
 
-    match apply_dest_path(
-        &environment_configuration.source_path,
-        &destination_configuration.path,
-    ) {
-        Err(e) => return Err(e),
+fn sync_in_work_path(
+    environment_configuration: EnvironmentConfiguration,
+    additional_ignore_list: Vec<PathPattern>,
+) -> Result<(GitDiffResult, bool), SyncError> {
+    let work_path = environment_configuration.work_path.clone();
+    // Left over if an earlier sync ran in the same working directory.
+    match rm(&work_path) {
+        Err(e) => return Err(SyncError::Internal(e)),
+        Ok(_) => {}
+    }
+    match mkdir_all(&work_path) {
+        Err(e) => return Err(SyncError::Internal(e)),
         Ok(_) => {}
     }
+    let result = sync_destination(environment_configuration, additional_ignore_list);
+    // The scratch directories are removed even if the sync failed.
+    match rm(&work_path) {
+        Err(e) if result.is_ok() => Err(SyncError::Internal(e)),
+        _ => result,
+    }
+}
 
-    match apply_lock_list(
-        &environment_configuration.source_path,
-        &destination_configuration.lock_list,
+fn sync_manifest_destination(
+    shared_configuration: &EnvironmentConfiguration,
+    destination: ManifestDestination,
+    index: usize,
+) -> Result<(GitDiffResult, bool), SyncError> {
+    let environment_configuration = match get_manifest_destination_configuration(
+        shared_configuration,
+        &destination,
+        format!("{WORK_PATH}/{index}"),
     ) {
-        Err(e) => return Err(e),
+        Err(e) => return Err(SyncError::Configuration(e)),
+        Ok(c) => c,
+    };
+    sync_in_work_path(environment_configuration, destination.ignore_list)
+}
+
+struct DestinationOutcome {
+    // None unless the destination is listed in the manifest
+    name: Option<String>,
+    result: Result<(GitDiffResult, bool), SyncError>,
+    report: JsonValue,
+}
+
+fn print_outcome(outcome: &DestinationOutcome) {
+    match &outcome.result {
+        Err(e) => {
+            print_error(e.message());
+            match &outcome.name {
+                None => print_error("Sync stopped due to an error"),
+                Some(name) => print_error(&format!(
+                    "Sync to destination {name} stopped due to an error"
+                )),
+            }
+        }
+        Ok((GitDiffResult::NoChanges, _)) => {
+            print_line("No changes detected in source. Destination repository was not updated.")
+        }
+        Ok((GitDiffResult::Changes(changes), dry_run)) => {
+            if *dry_run {
+                print_line("Dry run: destination repository was not updated. The sync would make these changes:");
+                print_line(&describe_changes(changes));
+            } else {
+                print_line("Sync successful");
+            }
+        }
+    }
+}
+
+fn finish_destination(
+    name: Option<String>,
+    result: Result<(GitDiffResult, bool), SyncError>,
+) -> DestinationOutcome {
+    if let Some(n) = &name {
+        report_destination_name(n);
+    }
+    report_outcome(&result);
+    let outcome = DestinationOutcome {
+        name: name,
+        result: result,
+        report: take_report(),
+    };
+    print_outcome(&outcome);
+    outcome
+}
+
+// Every destination is synced, even if an earlier one failed.
+fn sync_manifest(destinations: Vec<ManifestDestination>) -> Vec<DestinationOutcome> {
+    if has_destination_input() {
+        return vec![finish_destination(
+            None,
+            Err(SyncError::Configuration(format!(
+                "Expected input destination-repository not to be defined when {MANIFEST_FILE_NAME} exists"
+            ))),
+        )];
+    }
+    let shared_configuration = match get_shared_configuration() {
+        Err(e) => return vec![finish_destination(None, Err(SyncError::Configuration(e)))],
+        Ok(c) => c,
+    };
+    // Each worker takes the next destination from the queue until it is empty,
+    // so at most max_parallel destinations are synced at the same time.
+    let worker_count = shared_configuration.max_parallel.min(destinations.len());
+    let queue = Mutex::new(destinations.into_iter().enumerate());
+    let finished: Mutex<Vec<(usize, DestinationOutcome)>> = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0..worker_count {
+            scope.spawn(|| loop {
+                let next = match queue.lock() {
+                    Err(poisoned) => poisoned.into_inner().next(),
+                    Ok(mut q) => q.next(),
+                };
+                let (index, destination) = match next {
+                    None => break,
+                    Some(d) => d,
+                };
+                set_output_prefix(&format!("[{}] ", destination.name));
+                print_line("Syncing destination");
+                let name = destination.name.clone();
+                let result = sync_manifest_destination(&shared_configuration, destination, index);
+                let outcome = finish_destination(Some(name), result);
+                set_output_prefix("");
+                match finished.lock() {
+                    Err(poisoned) => poisoned.into_inner().push((index, outcome)),
+                    Ok(mut f) => f.push((index, outcome)),
+                }
+            });
+        }
+    });
+    let mut finished = match finished.into_inner() {
+        Err(poisoned) => poisoned.into_inner(),
+        Ok(f) => f,
+    };
+    // The outcomes are kept in the order of the manifest.
+    finished.sort_by_key(|(index, _)| *index);
+    let outcomes: Vec<DestinationOutcome> =
+        finished.into_iter().map(|(_, outcome)| outcome).collect();
+    match rm(WORK_PATH) {
+        Err(e) => print_error(&e),
         Ok(_) => {}
     }
+    outcomes
+}
 
-    git_upload(
-        &environment_configuration.source_path,
-        &environment_configuration.dest_repo_url,
-        &environment_configuration.dest_pat,
-    )
+fn print_manifest_summary(outcomes: &[DestinationOutcome]) {
+    print_line(&format!("Summary of {} destinations:", outcomes.len()));
+    for outcome in outcomes.iter() {
+        let result = match &outcome.result {
+            Err(e) => format!("failed with exit code {}", e.exit_code()),
+            Ok((GitDiffResult::NoChanges, _)) => String::from("no changes"),
+            Ok((GitDiffResult::Changes(changes), dry_run)) => {
+                if *dry_run {
+                    format!("dry run, {} files would change", changes.len())
+                } else {
+                    format!("{} files changed", changes.len())
+                }
+            }
+        };
+        print_line(&format!(
+            "  {}: {result}",
+            outcome.name.as_deref().unwrap_or("(manifest)")
+        ));
+    }
 }
 
 fn main() -> ExitCode {
-    match main_impl() {
+    if let Some(exit_code) = answer_askpass_prompt() {
+        return exit_code;
+    }
+    let (outcomes, is_manifest) = match get_manifest(SOURCE_REPOSITORY_PATH) {
+        Err(e) => (
+            vec![finish_destination(None, Err(SyncError::Configuration(e)))],
+            false,
+        ),
+        Ok(None) => (vec![finish_destination(None, main_impl())], false),
+        Ok(Some(destinations)) => (sync_manifest(destinations), true),
+    };
+    let reports: Vec<JsonValue> = outcomes.iter().map(|o| o.report.clone()).collect();
+    let mut reporting_failed = false;
+    match write_report(&reports, is_manifest) {
         Err(e) => {
-            eprintln!("{}", e);
-            eprintln!("Sync stopped due to an error");
-            ExitCode::FAILURE
+            print_error(&e);
+            reporting_failed = true;
         }
-        Ok(git_diff_result) => {
-            println!(
-                "{}",
-                match git_diff_result {
-                    GitDiffResult::NoChanges =>
-                        "No changes detected in source. Destination repository was not updated.",
-                    GitDiffResult::Changes => "Sync successful",
-                }
-            );
-            ExitCode::SUCCESS
+        Ok(_) => {}
+    }
+    match write_github_actions_results(&reports) {
+        Err(e) => {
+            print_error(&e);
+            reporting_failed = true;
+        }
+        Ok(_) => {}
+    }
+    if is_manifest {
+        print_manifest_summary(&outcomes);
+    }
+    // The exit code of the first failed destination is used.
+    for outcome in outcomes.iter() {
+        if let Err(e) = &outcome.result {
+            return ExitCode::from(e.exit_code());
         }
     }
+    // The sync itself succeeded, but a workflow relying on the report
+    // or the step outputs should still fail.
+    if reporting_failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
 }
@@ -0,0 +1,206 @@
+use std::time::Instant;
+
+use crate::commands::{
+    mkdir, mkdir_all, mv, rm, GitDiffResult, SourceCommit, SOURCE_COMMIT_TRAILER,
+};
+use crate::commit_message::{
+    render_commit_message, select_commit_message_template, CommitMessageContext,
+};
+use crate::get_environment_configuration::{
+    default_git_identity, in_work_path, EnvironmentConfiguration, SOURCE_REPOSITORY_PATH,
+};
+use crate::get_json_configuration::SourceConfiguration;
+use crate::git_backend::GitBackend;
+use crate::run_report::{report_commit, report_phase, report_push};
+use crate::sync_error::SyncError;
+use crate::transformations::{
+    apply_destination_transformations, apply_source_transformations, git_stage,
+    is_sync_branch_up_to_date,
+};
+
+const SNAPSHOT_PATH: &str = "snapshot";
+
+// Creates one commit in the destination repository for the given source commit.
+// The commit is exported to the snapshot directory, and its transformed tree is
+// built in the staging directory. Afterwards, the staging directory (which holds
+// the .git directory and the locked files) replaces the destination directory,
+// so the next commit is built on top of it.
+fn mirror_commit(
+    git: &dyn GitBackend,
+    commit: &SourceCommit,
+    environment_configuration: &EnvironmentConfiguration,
+    source_configuration: &SourceConfiguration,
+) -> Result<(), SyncError> {
+    let work_path = environment_configuration.work_path.as_str();
+    let snapshot_root = in_work_path(work_path, SNAPSHOT_PATH);
+    let staging_path = in_work_path(work_path, "staging");
+    let destination_directory = in_work_path(work_path, "destination");
+    match rm(&snapshot_root) {
+        Err(e) => return Err(SyncError::Internal(e)),
+        Ok(_) => {}
+    }
+    match mkdir(&snapshot_root) {
+        Err(e) => return Err(SyncError::Internal(e)),
+        Ok(_) => {}
+    }
+    match git.export_commit(SOURCE_REPOSITORY_PATH, &commit.sha, &snapshot_root) {
+        Err(e) => return Err(SyncError::Internal(e)),
+        Ok(_) => {}
+    }
+    let snapshot_path = match &environment_configuration.source_subpath {
+        None => snapshot_root.clone(),
+        Some(subpath) => format!("{snapshot_root}/{subpath}"),
+    };
+    // source-path may not exist in older commits
+    match mkdir_all(&snapshot_path) {
+        Err(e) => return Err(SyncError::Internal(e)),
+        Ok(_) => {}
+    }
+    let started = Instant::now();
+    match apply_source_transformations(&snapshot_path, &staging_path, source_configuration) {
+        Err(e) => return Err(SyncError::Transformation(e)),
+        Ok(_) => {}
+    }
+    report_phase("source_transformations", started);
+    let started = Instant::now();
+    let destination_configuration =
+        match apply_destination_transformations(&staging_path, work_path) {
+            Err(e) => return Err(SyncError::Transformation(e)),
+            Ok(c) => c,
+        };
+    report_phase("destination_transformations", started);
+    match git_stage(git, &staging_path, &source_configuration.secret_scan) {
+        Err(e) => return Err(e),
+        Ok(GitDiffResult::NoChanges) => {}
+        Ok(GitDiffResult::Changes(changes)) => {
+            let commit_message = match render_commit_message(
+                select_commit_message_template(
+                    &environment_configuration.commit_message,
+                    &destination_configuration.commit_message,
+                ),
+                &CommitMessageContext {
+                    source_commit: Some(commit),
+                    source_ref: environment_configuration.source_ref.as_deref(),
+                    timestamp: commit.timestamp,
+                    changes: &changes,
+                    include_source_subject: environment_configuration.include_source_subject,
+                },
+            ) {
+                Err(e) => return Err(SyncError::Configuration(e)),
+                Ok(m) => m,
+            };
+            // Authors who have not opted in to a public identity
+            // are anonymized to the default identity.
+            let committer = default_git_identity(environment_configuration);
+            let author = match source_configuration
+                .authors
+                .get(&commit.author_email.to_lowercase())
+            {
+                None => committer.clone(),
+                Some(identity) => identity.clone(),
+            };
+            match git.commit(
+                &staging_path,
+                &format!(
+                    "{commit_message}\n\n{SOURCE_COMMIT_TRAILER}: {}",
+                    commit.sha
+                ),
+                &author,
+                &committer,
+            ) {
+                Err(e) => return Err(SyncError::Internal(e)),
+                Ok(_) => {}
+            }
+        }
+    }
+    match rm(&destination_directory) {
+        Err(e) => return Err(SyncError::Internal(e)),
+        Ok(_) => {}
+    }
+    match mv(&staging_path, &destination_directory) {
+        Err(e) => return Err(SyncError::Internal(e)),
+        Ok(_) => {}
+    }
+    match rm(&snapshot_root) {
+        Err(e) => Err(SyncError::Internal(e)),
+        Ok(_) => Ok(()),
+    }
+}
+
+// Replays every source commit since the last synced one as its own destination commit.
+// The current source configuration is used for every commit, so that newly
+// ignored files are not published from older commits.
+// Must be called after the destination repository is cloned.
+pub(crate) fn mirror_history(
+    git: &dyn GitBackend,
+    environment_configuration: &EnvironmentConfiguration,
+    source_configuration: &SourceConfiguration,
+    dest_branch: &str,
+) -> Result<GitDiffResult, SyncError> {
+    let destination_directory = in_work_path(&environment_configuration.work_path, "destination");
+    let initial_head = match git.head(&destination_directory) {
+        Err(e) => return Err(SyncError::Internal(e)),
+        Ok(sha) => sha,
+    };
+    // If the last synced commit is unknown, or no longer part of the source
+    // history, only the current source commit is synced.
+    let since = match git.last_synced_commit(&destination_directory) {
+        Err(e) => return Err(SyncError::Internal(e)),
+        Ok(None) => None,
+        Ok(Some(sha)) => {
+            if git.is_ancestor(SOURCE_REPOSITORY_PATH, &sha, "HEAD") {
+                Some(sha)
+            } else {
+                None
+            }
+        }
+    };
+    let commits = match git.list_commits(SOURCE_REPOSITORY_PATH, since.as_deref()) {
+        Err(e) => return Err(SyncError::Internal(e)),
+        Ok(c) => c,
+    };
+    for commit in commits.iter() {
+        match mirror_commit(git, commit, environment_configuration, source_configuration) {
+            Err(e) => return Err(e),
+            Ok(_) => {}
+        }
+    }
+    let changes = match git.diff_since(&destination_directory, &initial_head) {
+        Err(e) => return Err(SyncError::Internal(e)),
+        Ok(diff) => match diff {
+            GitDiffResult::NoChanges => return Ok(GitDiffResult::NoChanges),
+            GitDiffResult::Changes(c) => c,
+        },
+    };
+    if environment_configuration.dry_run {
+        return Ok(GitDiffResult::Changes(changes));
+    }
+    if is_sync_branch_up_to_date(
+        git,
+        &destination_directory,
+        environment_configuration,
+        dest_branch,
+    ) {
+        return Ok(GitDiffResult::NoChanges);
+    }
+    match git.head(&destination_directory) {
+        Err(e) => return Err(SyncError::Internal(e)),
+        Ok(sha) => report_commit(&sha),
+    }
+    let push_result = git.push(
+        &destination_directory,
+        &environment_configuration.dest_repo_url,
+        dest_branch,
+        environment_configuration.pull_request,
+    );
+    report_push(
+        dest_branch,
+        environment_configuration.pull_request,
+        push_result.is_ok(),
+    );
+    match push_result {
+        Err(e) => return Err(e),
+        Ok(_) => {}
+    }
+    Ok(GitDiffResult::Changes(changes))
+}
@@ -0,0 +1,963 @@
+use std::collections::HashSet;
+use std::fs::{create_dir_all, read, read_dir, read_link, remove_dir_all, remove_file, write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+
+use gix::bstr::{BStr, BString, ByteSlice};
+use gix::credentials::helper::Action;
+use gix::credentials::protocol::Outcome;
+use gix::diff::blob::intern::InternedInput;
+use gix::diff::blob::sink::Counter;
+use gix::diff::blob::sources::byte_lines_with_terminator;
+use gix::diff::blob::Algorithm;
+use gix::diff::Rewrites;
+use gix::index::entry::Mode;
+use gix::object::tree::diff::ChangeDetached;
+use gix::objs::tree::EntryKind;
+use gix::progress::Discard;
+use gix::refs::transaction::{Change, LogChange, PreviousValue, RefEdit};
+use gix::refs::{FullName, Target};
+use gix::sec::identity::Account;
+use gix::{ObjectId, Repository};
+
+use crate::commands::{
+    ChangeKind, FileChange, GitDiffResult, GitIdentity, SourceCommit, SOURCE_COMMIT_TRAILER,
+};
+use crate::get_environment_configuration::EnvironmentConfiguration;
+use crate::git_backend::GitBackend;
+use crate::native_git_push::push_commit;
+use crate::sync_error::SyncError;
+
+// How gix reports credentials that the remote refused
+const REJECTED_CREDENTIALS: &str = "were not accepted by the remote";
+
+// Uses gix, a git implementation written in Rust, so that neither git
+// nor any system library has to be installed.
+// Only https:// and file:// remotes are supported, and file:// remotes
+// are reached through git-upload-pack and git-receive-pack.
+pub(crate) struct NativeGitBackend {
+    username: Option<String>,
+    pat: Option<String>,
+    // Recorded in the reflog when a branch is checked out
+    identity: GitIdentity,
+}
+
+impl NativeGitBackend {
+    pub(crate) fn new(
+        environment_configuration: &EnvironmentConfiguration,
+    ) -> Result<NativeGitBackend, String> {
+        if environment_configuration.dest_ssh_key.is_some() {
+            return Err(String::from(
+                "The native git backend does not support destination-ssh-key",
+            ));
+        }
+        Ok(NativeGitBackend {
+            username: environment_configuration.dest_pat_user.clone(),
+            pat: environment_configuration.dest_pat.clone(),
+            identity: GitIdentity {
+                name: environment_configuration.git_name.clone(),
+                email: environment_configuration.git_email.clone(),
+            },
+        })
+    }
+}
+
+fn open_repository(cwd: &str) -> Result<Repository, String> {
+    match gix::open(cwd) {
+        Err(e) => Err(format!("Unable to open git repository {cwd}: {e}")),
+        Ok(r) => Ok(r),
+    }
+}
+
+fn describe_error(operation: &str, e: impl std::fmt::Display) -> String {
+    format!("git {operation} failed: {e}")
+}
+
+// Includes the causes, which is where gix reports HTTP status codes.
+fn describe_error_chain(operation: &str, e: &dyn std::error::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(s) = source {
+        message.push_str(&format!(": {s}"));
+        source = s.source();
+    }
+    describe_error(operation, message)
+}
+
+fn path_to_string(path: &BStr) -> Result<String, String> {
+    match path.to_str() {
+        Err(_) => Err(String::from(
+            "Internal Error: file name is not valid Unicode",
+        )),
+        Ok(s) => Ok(String::from(s)),
+    }
+}
+
+fn blob_data(repository: &Repository, id: Option<ObjectId>) -> Result<Vec<u8>, String> {
+    match id {
+        None => Ok(Vec::new()),
+        Some(id) => match repository.find_object(id) {
+            Err(e) => Err(describe_error("diff", e)),
+            Ok(o) => Ok(o.detach().data),
+        },
+    }
+}
+
+// Like git, a file with a NUL byte near its start is treated as binary,
+// and binary files have no line counts.
+fn line_stats(
+    repository: &Repository,
+    old: Option<ObjectId>,
+    new: Option<ObjectId>,
+) -> Result<(Option<usize>, Option<usize>), String> {
+    let old = match blob_data(repository, old) {
+        Err(e) => return Err(e),
+        Ok(d) => d,
+    };
+    let new = match blob_data(repository, new) {
+        Err(e) => return Err(e),
+        Ok(d) => d,
+    };
+    let is_binary = |data: &[u8]| data.iter().take(8000).any(|b| *b == 0);
+    if is_binary(&old) || is_binary(&new) {
+        return Ok((None, None));
+    }
+    let input = InternedInput::new(
+        byte_lines_with_terminator(&old),
+        byte_lines_with_terminator(&new),
+    );
+    let counter = gix::diff::blob::diff(Algorithm::Myers, &input, Counter::default());
+    Ok((
+        Some(counter.insertions as usize),
+        Some(counter.removals as usize),
+    ))
+}
+
+fn diff_trees(
+    repository: &Repository,
+    old_tree: Option<ObjectId>,
+    new_tree: Option<ObjectId>,
+) -> Result<GitDiffResult, String> {
+    let find_tree = |id: Option<ObjectId>| match id {
+        None => Ok(repository.empty_tree()),
+        Some(id) => repository.find_tree(id),
+    };
+    let (old_tree, new_tree) = match (find_tree(old_tree), find_tree(new_tree)) {
+        (Ok(o), Ok(n)) => (o, n),
+        (Err(e), _) | (_, Err(e)) => return Err(describe_error("diff", e)),
+    };
+    let options = gix::diff::Options::default().with_rewrites(Some(Rewrites::default()));
+    let detached = match repository.diff_tree_to_tree(&old_tree, &new_tree, options) {
+        Err(e) => return Err(describe_error("diff", e)),
+        Ok(d) => d,
+    };
+    let mut changes: Vec<FileChange> = Vec::new();
+    for change in detached {
+        let (kind, path, previous_path, old_id, new_id) = match change {
+            ChangeDetached::Addition {
+                location,
+                entry_mode,
+                id,
+                ..
+            } if !entry_mode.is_tree() => (ChangeKind::Added, location, None, None, Some(id)),
+            ChangeDetached::Deletion {
+                location,
+                entry_mode,
+                id,
+                ..
+            } if !entry_mode.is_tree() => (ChangeKind::Deleted, location, None, Some(id), None),
+            ChangeDetached::Modification {
+                location,
+                previous_id,
+                entry_mode,
+                id,
+                ..
+            } if !entry_mode.is_tree() => (
+                ChangeKind::Modified,
+                location,
+                None,
+                Some(previous_id),
+                Some(id),
+            ),
+            ChangeDetached::Rewrite {
+                source_location,
+                source_id,
+                location,
+                id,
+                ..
+            } => (
+                ChangeKind::Renamed,
+                location,
+                Some(source_location),
+                Some(source_id),
+                Some(id),
+            ),
+            _ => continue,
+        };
+        let path = match path_to_string(path.as_ref()) {
+            Err(e) => return Err(e),
+            Ok(p) => p,
+        };
+        let previous_path = match previous_path.map(|p| path_to_string(p.as_ref())) {
+            None => None,
+            Some(Err(e)) => return Err(e),
+            Some(Ok(p)) => Some(p),
+        };
+        let (lines_added, lines_deleted) = match line_stats(repository, old_id, new_id) {
+            Err(e) => return Err(e),
+            Ok(s) => s,
+        };
+        changes.push(FileChange {
+            kind: kind,
+            path: path,
+            previous_path: previous_path,
+            lines_added: lines_added,
+            lines_deleted: lines_deleted,
+        });
+    }
+    // In the same order as git diff prints them.
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    if changes.is_empty() {
+        Ok(GitDiffResult::NoChanges)
+    } else {
+        Ok(GitDiffResult::Changes(changes))
+    }
+}
+
+// Returns None for an unborn HEAD.
+fn head_tree(repository: &Repository) -> Option<ObjectId> {
+    match repository.head_tree_id() {
+        Err(_) => None,
+        Ok(id) => Some(id.detach()),
+    }
+}
+
+fn empty_tree_id() -> ObjectId {
+    ObjectId::empty_tree(gix::hash::Kind::Sha1)
+}
+
+// Writes the tree that a commit of the index would have.
+fn write_index_tree(repository: &Repository) -> Result<ObjectId, String> {
+    let index = match repository.index_or_empty() {
+        Err(e) => return Err(describe_error("write-tree", e)),
+        Ok(i) => i,
+    };
+    let mut editor = match repository.edit_tree(empty_tree_id()) {
+        Err(e) => return Err(describe_error("write-tree", e)),
+        Ok(e) => e,
+    };
+    for entry in index.entries() {
+        let mode = match entry.mode.to_tree_entry_mode() {
+            None => continue,
+            Some(m) => m,
+        };
+        match editor.upsert(entry.path(&index), mode.kind(), entry.id) {
+            Err(e) => return Err(describe_error("write-tree", e)),
+            Ok(_) => {}
+        }
+    }
+    match editor.write() {
+        Err(e) => Err(describe_error("write-tree", e)),
+        Ok(id) => Ok(id.detach()),
+    }
+}
+
+fn write_index_from_tree(repository: &Repository, tree: ObjectId) -> Result<(), String> {
+    match repository.index_from_tree(&tree) {
+        Err(e) => Err(describe_error("read-tree", e)),
+        Ok(mut index) => match index.write(Default::default()) {
+            Err(e) => Err(describe_error("read-tree", e)),
+            Ok(_) => Ok(()),
+        },
+    }
+}
+
+fn write_tree(repository: &Repository, tree: ObjectId, directory: &Path) -> Result<(), String> {
+    let tree = match repository.find_tree(tree) {
+        Err(e) => return Err(describe_error("checkout-index", e)),
+        Ok(t) => t,
+    };
+    let entries: Vec<(BString, EntryKind, ObjectId)> = match tree.decode() {
+        Err(e) => return Err(describe_error("checkout-index", e)),
+        Ok(t) => t
+            .entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.filename.to_owned(),
+                    entry.mode.kind(),
+                    entry.oid.to_owned(),
+                )
+            })
+            .collect(),
+    };
+    for (name, kind, id) in entries {
+        let name = match path_to_string(name.as_ref()) {
+            Err(e) => return Err(e),
+            Ok(n) => n,
+        };
+        let path = directory.join(name);
+        let data = match kind {
+            EntryKind::Blob | EntryKind::BlobExecutable | EntryKind::Link => {
+                match blob_data(repository, Some(id)) {
+                    Err(e) => return Err(e),
+                    Ok(d) => d,
+                }
+            }
+            _ => Vec::new(),
+        };
+        let result = match kind {
+            EntryKind::Tree => match create_dir_all(&path) {
+                Err(e) => Err(e),
+                Ok(_) => match write_tree(repository, id, &path) {
+                    Err(e) => return Err(e),
+                    Ok(_) => Ok(()),
+                },
+            },
+            EntryKind::Link => match std::str::from_utf8(&data) {
+                Err(_) => {
+                    return Err(String::from(
+                        "Internal Error: symbolic link target is not valid Unicode",
+                    ))
+                }
+                Ok(target) => symlink(target, &path),
+            },
+            EntryKind::Blob | EntryKind::BlobExecutable => {
+                let mode = if kind == EntryKind::BlobExecutable {
+                    0o755
+                } else {
+                    0o644
+                };
+                write(&path, &data).and_then(|_| {
+                    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode))
+                })
+            }
+            // Submodules are not exported, in the same way as git checkout-index.
+            EntryKind::Commit => Ok(()),
+        };
+        match result {
+            Err(e) => return Err(format!("Unable to write {}: {e}", path.display())),
+            Ok(_) => {}
+        }
+    }
+    Ok(())
+}
+
+// Removes everything in the working tree except the repository itself.
+fn clear_working_tree(directory: &Path) -> Result<(), String> {
+    let entries = match read_dir(directory) {
+        Err(e) => return Err(format!("Unable to read {}: {e}", directory.display())),
+        Ok(e) => e,
+    };
+    for entry in entries {
+        let entry = match entry {
+            Err(e) => return Err(format!("Unable to read {}: {e}", directory.display())),
+            Ok(e) => e,
+        };
+        if entry.file_name() == ".git" {
+            continue;
+        }
+        let path = entry.path();
+        let result = match entry.file_type() {
+            Ok(t) if t.is_dir() => remove_dir_all(&path),
+            _ => remove_file(&path),
+        };
+        match result {
+            Err(e) => return Err(format!("Unable to remove {}: {e}", path.display())),
+            Ok(_) => {}
+        }
+    }
+    Ok(())
+}
+
+struct WorkingTreeWalk<'a> {
+    repository: &'a Repository,
+    root: &'a Path,
+    tracked: HashSet<BString>,
+    excludes: gix::AttributeStack<'a>,
+    editor: gix::object::tree::Editor<'a>,
+}
+
+impl WorkingTreeWalk<'_> {
+    fn is_excluded(&mut self, relative_path: &str, mode: Mode) -> Result<bool, String> {
+        match self.excludes.at_path(relative_path, Some(mode)) {
+            Err(e) => Err(describe_error("add", e)),
+            Ok(platform) => Ok(platform.is_excluded()),
+        }
+    }
+
+    // Like git add -A, untracked files that are ignored are left out,
+    // and so are the files in an ignored directory.
+    fn add_directory(&mut self, relative_directory: &str, is_excluded: bool) -> Result<(), String> {
+        let directory = self.root.join(relative_directory);
+        let entries = match read_dir(&directory) {
+            Err(e) => return Err(format!("Unable to read {}: {e}", directory.display())),
+            Ok(e) => e,
+        };
+        for entry in entries {
+            let entry = match entry {
+                Err(e) => return Err(format!("Unable to read {}: {e}", directory.display())),
+                Ok(e) => e,
+            };
+            let name = match entry.file_name().to_str() {
+                None => {
+                    return Err(String::from(
+                        "Internal Error: file name is not valid Unicode",
+                    ))
+                }
+                Some(n) => String::from(n),
+            };
+            if relative_directory.is_empty() && name == ".git" {
+                continue;
+            }
+            let relative_path = if relative_directory.is_empty() {
+                name
+            } else {
+                format!("{relative_directory}/{name}")
+            };
+            let file_type = match entry.file_type() {
+                Err(e) => return Err(format!("Unable to read {relative_path}: {e}")),
+                Ok(t) => t,
+            };
+            if file_type.is_dir() {
+                let is_directory_excluded = is_excluded
+                    || match self.is_excluded(&relative_path, Mode::DIR) {
+                        Err(e) => return Err(e),
+                        Ok(x) => x,
+                    };
+                match self.add_directory(&relative_path, is_directory_excluded) {
+                    Err(e) => return Err(e),
+                    Ok(_) => {}
+                }
+                continue;
+            }
+            let mode = if file_type.is_symlink() {
+                Mode::SYMLINK
+            } else {
+                Mode::FILE
+            };
+            if !self.tracked.contains(relative_path.as_bytes().as_bstr()) {
+                let is_file_excluded = is_excluded
+                    || match self.is_excluded(&relative_path, mode) {
+                        Err(e) => return Err(e),
+                        Ok(x) => x,
+                    };
+                if is_file_excluded {
+                    continue;
+                }
+            }
+            let path = entry.path();
+            let (data, kind) = if file_type.is_symlink() {
+                match read_link(&path) {
+                    Err(e) => return Err(format!("Unable to read {relative_path}: {e}")),
+                    Ok(target) => (target.as_os_str().as_bytes().to_vec(), EntryKind::Link),
+                }
+            } else {
+                let is_executable = match entry.metadata() {
+                    Err(e) => return Err(format!("Unable to read {relative_path}: {e}")),
+                    Ok(m) => m.permissions().mode() & 0o111 != 0,
+                };
+                match read(&path) {
+                    Err(e) => return Err(format!("Unable to read {relative_path}: {e}")),
+                    Ok(d) => (
+                        d,
+                        if is_executable {
+                            EntryKind::BlobExecutable
+                        } else {
+                            EntryKind::Blob
+                        },
+                    ),
+                }
+            };
+            let id = match self.repository.write_blob(&data) {
+                Err(e) => return Err(describe_error("add", e)),
+                Ok(id) => id.detach(),
+            };
+            match self.editor.upsert(relative_path.as_str(), kind, id) {
+                Err(e) => return Err(describe_error("add", e)),
+                Ok(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+fn resolve_commit(repository: &Repository, revision: &str) -> Result<ObjectId, String> {
+    match repository
+        .rev_parse_single(revision)
+        .map_err(|e| describe_error("rev-parse", e))
+        .and_then(|id| id.object().map_err(|e| describe_error("rev-parse", e)))
+        .and_then(|o| {
+            o.peel_to_commit()
+                .map_err(|e| describe_error("rev-parse", e))
+        }) {
+        Err(e) => Err(e),
+        Ok(c) => Ok(c.id),
+    }
+}
+
+fn signature(identity: &GitIdentity) -> gix::actor::Signature {
+    gix::actor::Signature {
+        name: identity.name.as_str().into(),
+        email: identity.email.as_str().into(),
+        time: gix::date::Time::now_local_or_utc(),
+    }
+}
+
+// Points HEAD to branch, which is created at start unless the branch is unborn.
+fn set_head(
+    repository: &Repository,
+    branch: &str,
+    start: Option<ObjectId>,
+    committer: &GitIdentity,
+) -> Result<(), String> {
+    let (head, target) = match (
+        FullName::try_from("HEAD"),
+        FullName::try_from(format!("refs/heads/{branch}")),
+    ) {
+        (Ok(h), Ok(t)) => (h, t),
+        _ => return Err(format!("Invalid branch name {branch}")),
+    };
+    let mut edits: Vec<RefEdit> = Vec::new();
+    if let Some(commit) = start {
+        edits.push(RefEdit {
+            change: Change::Update {
+                log: LogChange::default(),
+                expected: PreviousValue::Any,
+                new: Target::Object(commit),
+            },
+            name: target.clone(),
+            deref: false,
+        });
+    }
+    edits.push(RefEdit {
+        change: Change::Update {
+            log: LogChange::default(),
+            expected: PreviousValue::Any,
+            new: Target::Symbolic(target),
+        },
+        name: head,
+        deref: false,
+    });
+    let committer = signature(committer);
+    let mut time = gix::date::parse::TimeBuf::default();
+    match repository.edit_references_as(edits, Some(committer.to_ref(&mut time))) {
+        Err(e) => Err(describe_error("checkout", e)),
+        Ok(_) => Ok(()),
+    }
+}
+
+// Removes the trailing whitespace of every line, the leading and trailing
+// blank lines, and repeated blank lines, in the same way as git commit.
+fn clean_up_message(message: &str) -> String {
+    let mut lines: Vec<&str> = Vec::new();
+    for line in message.lines().map(str::trim_end) {
+        if line.is_empty() && lines.last().is_none_or(|last| last.is_empty()) {
+            continue;
+        }
+        lines.push(line);
+    }
+    while lines.last() == Some(&"") {
+        lines.pop();
+    }
+    let mut cleaned = lines.join("\n");
+    cleaned.push('\n');
+    cleaned
+}
+
+impl GitBackend for NativeGitBackend {
+    // The type of the credentials callback is defined by gix.
+    #[allow(clippy::result_large_err)]
+    fn clone_destination(&self, repo_url: &str, directory: &str) -> Result<(), SyncError> {
+        let username = self.username.clone().unwrap_or_default();
+        let pat = self.pat.clone();
+        let prepare = match gix::prepare_clone(repo_url, directory) {
+            Err(e) => return Err(SyncError::Clone(describe_error_chain("clone", &e))),
+            Ok(p) => p,
+        };
+        let mut prepare = prepare.configure_connection(move |connection| {
+            let username = username.clone();
+            let pat = pat.clone();
+            connection.set_credentials(move |action| match (action, &pat) {
+                (Action::Get(context), Some(pat)) => Ok(Some(Outcome {
+                    identity: Account {
+                        username: username.clone(),
+                        password: pat.clone(),
+                        oauth_refresh_token: None,
+                    },
+                    next: context.into(),
+                })),
+                _ => Ok(None),
+            });
+            Ok(())
+        });
+        let interrupt = AtomicBool::new(false);
+        let result = prepare
+            .fetch_then_checkout(Discard, &interrupt)
+            .map_err(|e| describe_error_chain("clone", &e))
+            .and_then(|(mut checkout, _)| {
+                checkout
+                    .main_worktree(Discard, &interrupt)
+                    .map_err(|e| describe_error_chain("clone", &e))
+            });
+        match result {
+            Err(e) if e.contains(REJECTED_CREDENTIALS) => Err(SyncError::Authentication(e)),
+            Err(e) => Err(SyncError::Clone(e)),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    fn checkout_branch(&self, cwd: &str, branch: &str) -> Result<(), String> {
+        let repository = match open_repository(cwd) {
+            Err(e) => return Err(e),
+            Ok(r) => r,
+        };
+        let start = match repository.find_reference(&format!("refs/remotes/origin/{branch}")) {
+            Ok(mut remote_ref) => remote_ref.peel_to_id().ok().map(|id| id.detach()),
+            Err(_) => repository.head_id().ok().map(|id| id.detach()),
+        };
+        let result =
+            match start {
+                // An unborn branch only has to be renamed.
+                None => set_head(&repository, branch, None, &self.identity),
+                Some(commit) => set_head(&repository, branch, Some(commit), &self.identity)
+                    .and_then(|_| match repository.workdir() {
+                        None => Err(String::from(
+                            "Internal Error: repository has no working tree",
+                        )),
+                        Some(workdir) => match head_tree(&repository) {
+                            None => Err(format!("Unable to read the tree of {commit}")),
+                            Some(tree) => clear_working_tree(workdir)
+                                .and_then(|_| write_tree(&repository, tree, workdir))
+                                .and_then(|_| write_index_from_tree(&repository, tree)),
+                        },
+                    }),
+            };
+        match result {
+            Err(_) => Err(format!("Unable to check out destination branch {branch}")),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    fn add_all(&self, cwd: &str) -> Result<(), String> {
+        let repository = match open_repository(cwd) {
+            Err(e) => return Err(e),
+            Ok(r) => r,
+        };
+        let root = match repository.workdir() {
+            None => {
+                return Err(String::from(
+                    "Internal Error: repository has no working tree",
+                ))
+            }
+            Some(w) => w.to_path_buf(),
+        };
+        let index = match repository.index_or_empty() {
+            Err(e) => return Err(describe_error("add", e)),
+            Ok(i) => i,
+        };
+        let tracked: HashSet<BString> = index
+            .entries()
+            .iter()
+            .map(|entry| entry.path(&index).to_owned())
+            .collect();
+        let excludes = match repository.excludes(&index, None, Default::default()) {
+            Err(e) => return Err(describe_error("add", e)),
+            Ok(x) => x,
+        };
+        let editor = match repository.edit_tree(empty_tree_id()) {
+            Err(e) => return Err(describe_error("add", e)),
+            Ok(e) => e,
+        };
+        let mut walk = WorkingTreeWalk {
+            repository: &repository,
+            root: &root,
+            tracked: tracked,
+            excludes: excludes,
+            editor: editor,
+        };
+        match walk.add_directory("", false) {
+            Err(e) => return Err(e),
+            Ok(_) => {}
+        }
+        let tree = match walk.editor.write() {
+            Err(e) => return Err(describe_error("add", e)),
+            Ok(id) => id.detach(),
+        };
+        write_index_from_tree(&repository, tree)
+    }
+
+    fn diff(&self, cwd: &str) -> Result<GitDiffResult, String> {
+        let repository = match open_repository(cwd) {
+            Err(e) => return Err(e),
+            Ok(r) => r,
+        };
+        let index_tree = match write_index_tree(&repository) {
+            Err(e) => return Err(e),
+            Ok(t) => t,
+        };
+        diff_trees(&repository, head_tree(&repository), Some(index_tree))
+    }
+
+    fn diff_since(&self, cwd: &str, revision: &str) -> Result<GitDiffResult, String> {
+        let repository = match open_repository(cwd) {
+            Err(e) => return Err(e),
+            Ok(r) => r,
+        };
+        let old_tree = match resolve_commit(&repository, revision).and_then(|id| {
+            match repository.find_commit(id).map(|c| c.tree_id()) {
+                Ok(Ok(t)) => Ok(t.detach()),
+                Ok(Err(e)) => Err(describe_error("diff", e)),
+                Err(e) => Err(describe_error("diff", e)),
+            }
+        }) {
+            Err(e) => return Err(e),
+            Ok(t) => t,
+        };
+        diff_trees(&repository, Some(old_tree), head_tree(&repository))
+    }
+
+    fn commit(
+        &self,
+        cwd: &str,
+        commit_message: &str,
+        author: &GitIdentity,
+        committer: &GitIdentity,
+    ) -> Result<(), String> {
+        let repository = match open_repository(cwd) {
+            Err(e) => return Err(e),
+            Ok(r) => r,
+        };
+        let tree = match write_index_tree(&repository) {
+            Err(e) => return Err(e),
+            Ok(t) => t,
+        };
+        let (author, committer) = (signature(author), signature(committer));
+        let mut author_time = gix::date::parse::TimeBuf::default();
+        let mut committer_time = gix::date::parse::TimeBuf::default();
+        let parents: Vec<ObjectId> = repository
+            .head_id()
+            .ok()
+            .map(|id| id.detach())
+            .into_iter()
+            .collect();
+        match repository.commit_as(
+            committer.to_ref(&mut committer_time),
+            author.to_ref(&mut author_time),
+            "HEAD",
+            clean_up_message(commit_message),
+            tree,
+            parents,
+        ) {
+            Err(e) => Err(describe_error("commit", e)),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    fn push(&self, cwd: &str, repo_url: &str, branch: &str, force: bool) -> Result<(), SyncError> {
+        let repository = match open_repository(cwd) {
+            Err(e) => return Err(SyncError::Internal(e)),
+            Ok(r) => r,
+        };
+        let head = match repository.head_id() {
+            Err(e) => return Err(SyncError::Push(describe_error("push", e))),
+            Ok(id) => id.detach(),
+        };
+        push_commit(
+            &repository,
+            repo_url,
+            self.username.as_deref(),
+            self.pat.as_deref(),
+            head,
+            branch,
+            force,
+        )
+    }
+
+    fn head(&self, cwd: &str) -> Result<String, String> {
+        let repository = match open_repository(cwd) {
+            Err(e) => return Err(e),
+            Ok(r) => r,
+        };
+        match resolve_commit(&repository, "HEAD") {
+            Err(e) => Err(e),
+            Ok(id) => Ok(id.to_string()),
+        }
+    }
+
+    fn last_synced_commit(&self, cwd: &str) -> Result<Option<String>, String> {
+        let repository = match open_repository(cwd) {
+            Err(e) => return Err(e),
+            Ok(r) => r,
+        };
+        let head = match repository.head_id() {
+            // An empty repository has not been synced yet.
+            Err(_) => return Ok(None),
+            Ok(id) => id.detach(),
+        };
+        let walk = match repository.rev_walk([head]).all() {
+            Err(e) => return Err(describe_error("log", e)),
+            Ok(w) => w,
+        };
+        for info in walk {
+            let commit = match info
+                .map_err(|e| describe_error("log", e))
+                .and_then(|i| i.object().map_err(|e| describe_error("log", e)))
+            {
+                Err(e) => return Err(e),
+                Ok(c) => c,
+            };
+            let message = match commit.message() {
+                Err(e) => return Err(describe_error("log", e)),
+                Ok(m) => m,
+            };
+            if let Some(body) = message.body() {
+                for trailer in body.trailers() {
+                    if trailer.token == SOURCE_COMMIT_TRAILER {
+                        return Ok(Some(trailer.value.to_str_lossy().trim().to_string()));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn is_ancestor(&self, cwd: &str, ancestor: &str, descendant: &str) -> bool {
+        let repository = match open_repository(cwd) {
+            Err(_) => return false,
+            Ok(r) => r,
+        };
+        let (ancestor, descendant) = match (
+            resolve_commit(&repository, ancestor),
+            resolve_commit(&repository, descendant),
+        ) {
+            (Ok(a), Ok(d)) => (a, d),
+            _ => return false,
+        };
+        ancestor == descendant
+            || match repository.merge_base(ancestor, descendant) {
+                Err(_) => false,
+                Ok(base) => base.detach() == ancestor,
+            }
+    }
+
+    fn same_tree(&self, cwd: &str, revision: &str, other_revision: &str) -> bool {
+        let repository = match open_repository(cwd) {
+            Err(_) => return false,
+            Ok(r) => r,
+        };
+        let tree = |revision: &str| {
+            resolve_commit(&repository, revision).and_then(|id| {
+                match repository.find_commit(id).map(|c| c.tree_id()) {
+                    Ok(Ok(tree)) => Ok(tree.detach()),
+                    _ => Err(format!("Unable to read the tree of {revision}")),
+                }
+            })
+        };
+        match (tree(revision), tree(other_revision)) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    fn list_commits(&self, cwd: &str, since: Option<&str>) -> Result<Vec<SourceCommit>, String> {
+        let repository = match open_repository(cwd) {
+            Err(e) => return Err(e),
+            Ok(r) => r,
+        };
+        let head = match resolve_commit(&repository, "HEAD") {
+            Err(e) => return Err(e),
+            Ok(id) => id,
+        };
+        let mut hidden: Vec<ObjectId> = Vec::new();
+        if let Some(sha) = since {
+            match resolve_commit(&repository, sha) {
+                Err(e) => return Err(e),
+                Ok(id) => hidden.push(id),
+            }
+        }
+        let walk = match repository
+            .rev_walk([head])
+            .first_parent_only()
+            .with_hidden(hidden)
+            .all()
+        {
+            Err(e) => return Err(describe_error("log", e)),
+            Ok(w) => w,
+        };
+        let limit = if since.is_none() { 1 } else { usize::MAX };
+        let mut commits: Vec<SourceCommit> = Vec::new();
+        for info in walk.take(limit) {
+            let commit = match info
+                .map_err(|e| describe_error("log", e))
+                .and_then(|i| i.object().map_err(|e| describe_error("log", e)))
+            {
+                Err(e) => return Err(e),
+                Ok(c) => c,
+            };
+            let timestamp = match commit.time() {
+                Err(e) => return Err(describe_error("log", e)),
+                Ok(t) => t.seconds,
+            };
+            let author_email = match commit.author() {
+                Err(e) => return Err(describe_error("log", e)),
+                Ok(a) => a.email.to_str_lossy().to_string(),
+            };
+            let subject = match commit.message() {
+                Err(e) => return Err(describe_error("log", e)),
+                Ok(m) => m.summary().to_str_lossy().to_string(),
+            };
+            commits.push(SourceCommit {
+                sha: commit.id.to_string(),
+                timestamp: timestamp,
+                author_email: author_email,
+                subject: subject,
+            });
+        }
+        commits.reverse();
+        Ok(commits)
+    }
+
+    fn current_branch(&self, cwd: &str) -> Option<String> {
+        let repository = match open_repository(cwd) {
+            Err(_) => return None,
+            Ok(r) => r,
+        };
+        match repository.head_name() {
+            Ok(Some(name)) => name
+                .as_bstr()
+                .to_str()
+                .ok()
+                .and_then(|n| n.strip_prefix("refs/heads/"))
+                .map(String::from),
+            _ => None,
+        }
+    }
+
+    fn export_commit(
+        &self,
+        repository: &str,
+        sha: &str,
+        output_directory: &str,
+    ) -> Result<(), String> {
+        let repository = match open_repository(repository) {
+            Err(e) => return Err(e),
+            Ok(r) => r,
+        };
+        let tree = match ObjectId::from_hex(sha.as_bytes())
+            .map_err(|e| describe_error("read-tree", e))
+            .and_then(|id| {
+                repository
+                    .find_commit(id)
+                    .map_err(|e| describe_error("read-tree", e))
+            })
+            .and_then(|commit| commit.tree_id().map_err(|e| describe_error("read-tree", e)))
+        {
+            Err(e) => return Err(e),
+            Ok(t) => t.detach(),
+        };
+        write_tree(&repository, tree, Path::new(output_directory))
+    }
+}
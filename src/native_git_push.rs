@@ -0,0 +1,880 @@
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use gix::features::zlib::stream::deflate;
+use gix::objs::Kind;
+use gix::{ObjectId, Repository};
+use ureq::Agent;
+
+use crate::sync_error::{is_push_rejection, SyncError};
+
+// gix cannot push, so the receive-pack protocol is spoken here.
+// https:// remotes are reached over HTTP, and file:// remotes by running
+// git-receive-pack, in the same way as gix clones them with git-upload-pack.
+
+fn pkt_line(data: &str) -> Vec<u8> {
+    let mut line = format!("{:04x}", data.len() + 4).into_bytes();
+    line.extend_from_slice(data.as_bytes());
+    line
+}
+
+const FLUSH_PKT: &[u8] = b"0000";
+
+// Returns None for a flush packet.
+fn read_pkt_line(reader: &mut impl Read) -> Result<Option<Vec<u8>>, String> {
+    let mut length = [0u8; 4];
+    match reader.read_exact(&mut length) {
+        Err(_) => {
+            return Err(String::from(
+                "git push failed: the remote hung up unexpectedly",
+            ))
+        }
+        Ok(_) => {}
+    }
+    let length = match std::str::from_utf8(&length)
+        .ok()
+        .and_then(|l| usize::from_str_radix(l, 16).ok())
+    {
+        None => {
+            return Err(String::from(
+                "git push failed: invalid response from the remote",
+            ))
+        }
+        Some(l) => l,
+    };
+    if length < 4 {
+        return Ok(None);
+    }
+    let mut data = vec![0u8; length - 4];
+    match reader.read_exact(&mut data) {
+        Err(_) => Err(String::from(
+            "git push failed: the remote hung up unexpectedly",
+        )),
+        Ok(_) => Ok(Some(data)),
+    }
+}
+
+struct Advertisement {
+    refs: Vec<(ObjectId, String)>,
+    capabilities: Vec<String>,
+}
+
+fn read_advertisement(reader: &mut impl Read) -> Result<Advertisement, String> {
+    let mut advertisement = Advertisement {
+        refs: Vec::new(),
+        capabilities: Vec::new(),
+    };
+    loop {
+        let line = match read_pkt_line(reader) {
+            Err(e) => return Err(e),
+            Ok(None) => return Ok(advertisement),
+            Ok(Some(l)) => l,
+        };
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim_end_matches('\n');
+        let line = match line.split_once('\0') {
+            None => line,
+            Some((reference, capabilities)) => {
+                advertisement.capabilities = capabilities.split(' ').map(String::from).collect();
+                reference
+            }
+        };
+        let (id, name) = match line.split_once(' ') {
+            None => {
+                return Err(String::from(
+                    "git push failed: invalid response from the remote",
+                ))
+            }
+            Some(r) => r,
+        };
+        let id = match ObjectId::from_hex(id.as_bytes()) {
+            Err(_) => {
+                return Err(String::from(
+                    "git push failed: invalid response from the remote",
+                ))
+            }
+            Ok(i) => i,
+        };
+        // An empty repository only advertises its capabilities.
+        if name != "capabilities^{}" {
+            advertisement.refs.push((id, String::from(name)));
+        }
+    }
+}
+
+fn read_report_status(reader: &mut impl Read) -> Result<(), SyncError> {
+    loop {
+        let line = match read_pkt_line(reader) {
+            Err(e) => return Err(SyncError::Push(e)),
+            Ok(None) => return Ok(()),
+            Ok(Some(l)) => l,
+        };
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim_end_matches('\n');
+        if let Some(status) = line.strip_prefix("unpack ") {
+            if status != "ok" {
+                return Err(SyncError::Push(format!("git push failed: unpack {status}")));
+            }
+        } else if let Some(rejection) = line.strip_prefix("ng ") {
+            let message = format!("git push failed: {rejection}");
+            if is_push_rejection(rejection) {
+                return Err(SyncError::PushRejected(message));
+            }
+            return Err(SyncError::Push(message));
+        }
+    }
+}
+
+fn authorization(username: Option<&str>, pat: Option<&str>) -> Option<String> {
+    pat.map(|pat| {
+        format!(
+            "Basic {}",
+            STANDARD.encode(format!("{}:{pat}", username.unwrap_or("")))
+        )
+    })
+}
+
+fn describe_http_error(e: ureq::Error) -> SyncError {
+    match e {
+        ureq::Error::Status(code, _) if code == 401 || code == 403 => SyncError::Authentication(
+            format!("git push failed: the destination repository returned status {code}"),
+        ),
+        ureq::Error::Status(code, _) => SyncError::Push(format!(
+            "git push failed: the destination repository returned status {code}"
+        )),
+        ureq::Error::Transport(t) => SyncError::Push(format!("git push failed: {t}")),
+    }
+}
+
+fn read_body(response: ureq::Response) -> Result<Vec<u8>, SyncError> {
+    let mut body: Vec<u8> = Vec::new();
+    match response.into_reader().read_to_end(&mut body) {
+        Err(e) => Err(SyncError::Push(format!("git push failed: {e}"))),
+        Ok(_) => Ok(body),
+    }
+}
+
+enum Remote {
+    Http {
+        agent: Agent,
+        url: String,
+        authorization: Option<String>,
+    },
+    Process {
+        child: Child,
+        stdin: ChildStdin,
+        stdout: ChildStdout,
+    },
+}
+
+impl Remote {
+    fn connect(
+        repo_url: &str,
+        username: Option<&str>,
+        pat: Option<&str>,
+    ) -> Result<(Remote, Advertisement), SyncError> {
+        if let Some(path) = repo_url.strip_prefix("file://") {
+            let mut child = match Command::new("git")
+                .arg("receive-pack")
+                .arg(path)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+            {
+                Err(_) => {
+                    return Err(SyncError::Push(String::from(
+                        "Unable to run git-receive-pack, which is needed to push to file:// destinations",
+                    )))
+                }
+                Ok(c) => c,
+            };
+            let (stdin, mut stdout) = match (child.stdin.take(), child.stdout.take()) {
+                (Some(i), Some(o)) => (i, o),
+                _ => {
+                    return Err(SyncError::Internal(String::from(
+                        "Internal Error: unable to connect to git-receive-pack",
+                    )))
+                }
+            };
+            let advertisement = match read_advertisement(&mut stdout) {
+                Err(e) => return Err(SyncError::Push(e)),
+                Ok(a) => a,
+            };
+            return Ok((
+                Remote::Process {
+                    child: child,
+                    stdin: stdin,
+                    stdout: stdout,
+                },
+                advertisement,
+            ));
+        }
+        let agent = Agent::new();
+        let url = String::from(repo_url.trim_end_matches('/'));
+        let authorization = authorization(username, pat);
+        let mut request = agent
+            .get(&format!("{url}/info/refs"))
+            .query("service", "git-receive-pack")
+            .set("User-Agent", "yellow-chameleon");
+        if let Some(a) = &authorization {
+            request = request.set("Authorization", a);
+        }
+        let body = match request.call() {
+            Err(e) => return Err(describe_http_error(e)),
+            Ok(response) => match read_body(response) {
+                Err(e) => return Err(e),
+                Ok(b) => b,
+            },
+        };
+        let mut reader = body.as_slice();
+        // The smart HTTP protocol announces the service before the advertisement.
+        match read_pkt_line(&mut reader).and_then(|_| read_pkt_line(&mut reader)) {
+            Err(e) => return Err(SyncError::Push(e)),
+            Ok(_) => {}
+        }
+        let advertisement = match read_advertisement(&mut reader) {
+            Err(e) => return Err(SyncError::Push(e)),
+            Ok(a) => a,
+        };
+        Ok((
+            Remote::Http {
+                agent: agent,
+                url: url,
+                authorization: authorization,
+            },
+            advertisement,
+        ))
+    }
+
+    // Sends the request, and returns the report status if one was requested.
+    fn send(self, request: Option<Vec<u8>>, has_report_status: bool) -> Result<(), SyncError> {
+        match self {
+            Remote::Http {
+                agent,
+                url,
+                authorization,
+            } => {
+                let request_body = match request {
+                    None => return Ok(()),
+                    Some(r) => r,
+                };
+                let mut http_request = agent
+                    .post(&format!("{url}/git-receive-pack"))
+                    .set("Content-Type", "application/x-git-receive-pack-request")
+                    .set("Accept", "application/x-git-receive-pack-result")
+                    .set("User-Agent", "yellow-chameleon");
+                if let Some(a) = &authorization {
+                    http_request = http_request.set("Authorization", a);
+                }
+                let body = match http_request.send_bytes(&request_body) {
+                    Err(e) => return Err(describe_http_error(e)),
+                    Ok(response) => match read_body(response) {
+                        Err(e) => return Err(e),
+                        Ok(b) => b,
+                    },
+                };
+                if has_report_status {
+                    read_report_status(&mut body.as_slice())
+                } else {
+                    Ok(())
+                }
+            }
+            Remote::Process {
+                mut child,
+                mut stdin,
+                mut stdout,
+            } => {
+                // A flush packet alone ends the session without any update.
+                let request_body = request.unwrap_or_else(|| FLUSH_PKT.to_vec());
+                let sent = stdin.write_all(&request_body).and_then(|_| stdin.flush());
+                drop(stdin);
+                let result = match sent {
+                    Err(_) => Err(SyncError::Push(String::from(
+                        "git push failed: the remote hung up unexpectedly",
+                    ))),
+                    Ok(_) if has_report_status && request_body != FLUSH_PKT => {
+                        read_report_status(&mut stdout)
+                    }
+                    Ok(_) => Ok(()),
+                };
+                let _ = child.wait();
+                result
+            }
+        }
+    }
+}
+
+// Adds the objects of a tree that are not in seen yet.
+fn add_tree_objects(
+    repository: &Repository,
+    tree_id: ObjectId,
+    seen: &mut HashSet<ObjectId>,
+    objects: &mut Vec<ObjectId>,
+) -> Result<(), String> {
+    if !seen.insert(tree_id) {
+        return Ok(());
+    }
+    objects.push(tree_id);
+    let tree = match repository.find_tree(tree_id) {
+        Err(e) => return Err(format!("git push failed: {e}")),
+        Ok(t) => t,
+    };
+    let entries: Vec<(ObjectId, bool, bool)> = match tree.decode() {
+        Err(e) => return Err(format!("git push failed: {e}")),
+        Ok(t) => t
+            .entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.oid.to_owned(),
+                    entry.mode.is_tree(),
+                    entry.mode.is_commit(),
+                )
+            })
+            .collect(),
+    };
+    for (id, is_tree, is_submodule) in entries {
+        if is_tree {
+            match add_tree_objects(repository, id, seen, objects) {
+                Err(e) => return Err(e),
+                Ok(_) => {}
+            }
+        } else if !is_submodule && seen.insert(id) {
+            objects.push(id);
+        }
+    }
+    Ok(())
+}
+
+fn tree_of(repository: &Repository, commit: ObjectId) -> Result<ObjectId, String> {
+    match repository
+        .find_commit(commit)
+        .map_err(|e| e.to_string())
+        .and_then(|c| c.tree_id().map_err(|e| e.to_string()))
+    {
+        Err(e) => Err(format!("git push failed: {e}")),
+        Ok(id) => Ok(id.detach()),
+    }
+}
+
+// Lists the objects reachable from new that the remote does not have,
+// judging by the trees of the commits it has that the new commits build upon.
+fn objects_to_send(
+    repository: &Repository,
+    new: ObjectId,
+    haves: &[ObjectId],
+) -> Result<Vec<ObjectId>, String> {
+    let walk = match repository
+        .rev_walk([new])
+        .with_hidden(haves.iter().copied())
+        .all()
+    {
+        Err(e) => return Err(format!("git push failed: {e}")),
+        Ok(w) => w,
+    };
+    let mut commits: Vec<(ObjectId, Vec<ObjectId>)> = Vec::new();
+    for info in walk {
+        match info {
+            Err(e) => return Err(format!("git push failed: {e}")),
+            Ok(i) => commits.push((i.id, i.parent_ids().map(|p| p.detach()).collect())),
+        }
+    }
+    let new_commits: HashSet<ObjectId> = commits.iter().map(|(id, _)| *id).collect();
+    let mut seen: HashSet<ObjectId> = HashSet::new();
+    let mut known: Vec<ObjectId> = Vec::new();
+    for (_, parents) in commits.iter() {
+        for parent in parents.iter() {
+            if !new_commits.contains(parent) && repository.has_object(parent) {
+                match tree_of(repository, *parent)
+                    .and_then(|tree| add_tree_objects(repository, tree, &mut seen, &mut known))
+                {
+                    Err(e) => return Err(e),
+                    Ok(_) => {}
+                }
+            }
+        }
+    }
+    let mut objects: Vec<ObjectId> = Vec::new();
+    for (id, _) in commits.iter() {
+        objects.push(*id);
+        match tree_of(repository, *id)
+            .and_then(|tree| add_tree_objects(repository, tree, &mut seen, &mut objects))
+        {
+            Err(e) => return Err(e),
+            Ok(_) => {}
+        }
+    }
+    Ok(objects)
+}
+
+// Objects are stored whole rather than as deltas, which any remote accepts.
+fn write_pack(repository: &Repository, objects: &[ObjectId]) -> Result<Vec<u8>, String> {
+    let mut pack: Vec<u8> = Vec::new();
+    pack.extend_from_slice(b"PACK");
+    pack.extend_from_slice(&2u32.to_be_bytes());
+    pack.extend_from_slice(&(objects.len() as u32).to_be_bytes());
+    for id in objects.iter() {
+        let object = match repository.find_object(*id) {
+            Err(e) => return Err(format!("git push failed: {e}")),
+            Ok(o) => o,
+        };
+        let type_code: u8 = match object.kind {
+            Kind::Commit => 1,
+            Kind::Tree => 2,
+            Kind::Blob => 3,
+            Kind::Tag => 4,
+        };
+        let mut size = object.data.len();
+        let mut byte = (type_code << 4) | (size & 0x0f) as u8;
+        size >>= 4;
+        while size > 0 {
+            pack.push(byte | 0x80);
+            byte = (size & 0x7f) as u8;
+            size >>= 7;
+        }
+        pack.push(byte);
+        let mut encoder = deflate::Write::new(Vec::new());
+        match encoder
+            .write_all(&object.data)
+            .and_then(|_| encoder.flush())
+        {
+            Err(e) => return Err(format!("git push failed: {e}")),
+            Ok(_) => {}
+        }
+        pack.extend_from_slice(&encoder.into_inner());
+    }
+    let mut hasher = gix::hash::hasher(gix::hash::Kind::Sha1);
+    hasher.update(&pack);
+    match hasher.try_finalize() {
+        Err(e) => return Err(format!("git push failed: {e}")),
+        Ok(checksum) => pack.extend_from_slice(checksum.as_bytes()),
+    }
+    Ok(pack)
+}
+
+pub(crate) fn push_commit(
+    repository: &Repository,
+    repo_url: &str,
+    username: Option<&str>,
+    pat: Option<&str>,
+    new: ObjectId,
+    branch: &str,
+    force: bool,
+) -> Result<(), SyncError> {
+    let (remote, advertisement) = match Remote::connect(repo_url, username, pat) {
+        Err(e) => return Err(e),
+        Ok(r) => r,
+    };
+    let has_report_status = advertisement
+        .capabilities
+        .iter()
+        .any(|c| c == "report-status");
+    let reference = format!("refs/heads/{branch}");
+    let old = advertisement
+        .refs
+        .iter()
+        .find(|(_, name)| *name == reference)
+        .map(|(id, _)| *id)
+        .unwrap_or_else(|| ObjectId::null(gix::hash::Kind::Sha1));
+    if old == new {
+        return remote.send(None, has_report_status);
+    }
+    if !force && !old.is_null() {
+        let is_fast_forward = repository.has_object(old)
+            && match repository.merge_base(old, new) {
+                Err(_) => false,
+                Ok(base) => base.detach() == old,
+            };
+        if !is_fast_forward {
+            let _ = remote.send(None, has_report_status);
+            return Err(SyncError::PushRejected(format!(
+                "The push was rejected because destination branch {branch} has changed"
+            )));
+        }
+    }
+    let haves: Vec<ObjectId> = advertisement
+        .refs
+        .iter()
+        .map(|(id, _)| *id)
+        .filter(|id| repository.has_object(id))
+        .collect();
+    let pack = match objects_to_send(repository, new, &haves)
+        .and_then(|objects| write_pack(repository, &objects))
+    {
+        Err(e) => {
+            let _ = remote.send(None, has_report_status);
+            return Err(SyncError::Push(e));
+        }
+        Ok(p) => p,
+    };
+    let capabilities = if has_report_status {
+        "report-status agent=yellow-chameleon"
+    } else {
+        "agent=yellow-chameleon"
+    };
+    let mut request = pkt_line(&format!("{old} {new} {reference}\0{capabilities}\n"));
+    request.extend_from_slice(FLUSH_PKT);
+    request.extend_from_slice(&pack);
+    remote.send(Some(request), has_report_status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+    use std::io::{BufRead, BufReader};
+    use std::net::{TcpListener, TcpStream};
+    use std::path::{Path, PathBuf};
+    use std::process;
+    use std::thread;
+
+    const ZERO_ID: &str = "0000000000000000000000000000000000000000";
+    const COMMIT_ID: &str = "1111111111111111111111111111111111111111";
+
+    fn git(cwd: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(cwd)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "git {args:?} failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from(String::from_utf8(output.stdout).unwrap().trim_end())
+    }
+
+    fn scratch(name: &str) -> PathBuf {
+        let root = temp_dir().join(format!("yellow-chameleon-push-{}-{name}", process::id()));
+        let _ = remove_dir_all(&root);
+        create_dir_all(&root).unwrap();
+        root
+    }
+
+    // Creates a repository with two commits and a bare repository that only has the first.
+    fn set_up(root: &Path) -> (PathBuf, PathBuf) {
+        let local = root.join("local");
+        git(root, &["init", "-q", "-b", "main", "local"]);
+        write(local.join("a.txt"), "a\n").unwrap();
+        git(&local, &["add", "-A"]);
+        git(&local, &["commit", "-q", "-m", "first"]);
+        let remote = root.join("remote.git");
+        git(root, &["clone", "-q", "--bare", "local", "remote.git"]);
+        create_dir_all(local.join("dir")).unwrap();
+        write(local.join("dir/b.txt"), "b\n").unwrap();
+        write(local.join("a.txt"), "a\nchanged\n").unwrap();
+        git(&local, &["add", "-A"]);
+        git(&local, &["commit", "-q", "-m", "second"]);
+        (local, remote)
+    }
+
+    fn head(repository: &Repository) -> ObjectId {
+        repository.head_id().unwrap().detach()
+    }
+
+    fn pkt_lines(lines: &[&str]) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::new();
+        for line in lines {
+            data.extend_from_slice(&pkt_line(line));
+        }
+        data.extend_from_slice(FLUSH_PKT);
+        data
+    }
+
+    #[test]
+    fn pkt_line_round_trip() {
+        assert_eq!(pkt_line("a\n"), b"0006a\n");
+        let data = pkt_lines(&["first\n", "second"]);
+        let mut reader = data.as_slice();
+        assert_eq!(
+            read_pkt_line(&mut reader).unwrap(),
+            Some(b"first\n".to_vec())
+        );
+        assert_eq!(
+            read_pkt_line(&mut reader).unwrap(),
+            Some(b"second".to_vec())
+        );
+        assert_eq!(read_pkt_line(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn invalid_pkt_lines() {
+        assert!(read_pkt_line(&mut b"zzzz".as_slice()).is_err());
+        assert!(read_pkt_line(&mut b"0009ab".as_slice()).is_err());
+        assert!(read_pkt_line(&mut b"00".as_slice()).is_err());
+    }
+
+    #[test]
+    fn reads_refs_and_capabilities() {
+        let data = pkt_lines(&[
+            &format!("{COMMIT_ID} refs/heads/main\0report-status delete-refs ofs-delta\n"),
+            &format!("{ZERO_ID} refs/heads/other\n"),
+        ]);
+        let advertisement = read_advertisement(&mut data.as_slice()).unwrap();
+        assert_eq!(
+            advertisement.capabilities,
+            vec!["report-status", "delete-refs", "ofs-delta"]
+        );
+        assert_eq!(advertisement.refs.len(), 2);
+        assert_eq!(advertisement.refs[0].0.to_string(), COMMIT_ID);
+        assert_eq!(advertisement.refs[0].1, "refs/heads/main");
+        assert_eq!(advertisement.refs[1].1, "refs/heads/other");
+    }
+
+    #[test]
+    fn empty_repository_advertises_no_refs() {
+        let data = pkt_lines(&[&format!("{ZERO_ID} capabilities^{{}}\0report-status\n")]);
+        let advertisement = read_advertisement(&mut data.as_slice()).unwrap();
+        assert!(advertisement.refs.is_empty());
+        assert_eq!(advertisement.capabilities, vec!["report-status"]);
+    }
+
+    #[test]
+    fn invalid_advertisement() {
+        assert!(read_advertisement(&mut pkt_lines(&["not a ref\n"]).as_slice()).is_err());
+        assert!(
+            read_advertisement(&mut pkt_lines(&["nothex refs/heads/main\n"]).as_slice()).is_err()
+        );
+    }
+
+    #[test]
+    fn report_status() {
+        let ok = pkt_lines(&["unpack ok\n", "ok refs/heads/main\n"]);
+        assert!(read_report_status(&mut ok.as_slice()).is_ok());
+        let rejected = pkt_lines(&["unpack ok\n", "ng refs/heads/main non-fast-forward\n"]);
+        assert!(matches!(
+            read_report_status(&mut rejected.as_slice()),
+            Err(SyncError::PushRejected(_))
+        ));
+        let declined = pkt_lines(&[
+            "unpack ok\n",
+            "ng refs/heads/main pre-receive hook declined\n",
+        ]);
+        assert!(matches!(
+            read_report_status(&mut declined.as_slice()),
+            Err(SyncError::Push(_))
+        ));
+        let unpack_failed = pkt_lines(&["unpack index-pack abnormal exit\n"]);
+        assert!(matches!(
+            read_report_status(&mut unpack_failed.as_slice()),
+            Err(SyncError::Push(_))
+        ));
+    }
+
+    #[test]
+    fn pack_is_accepted_by_index_pack() {
+        let root = scratch("pack");
+        let (local, remote) = set_up(&root);
+        let repository = gix::open(&local).unwrap();
+        let new = head(&repository);
+        let have = ObjectId::from_hex(git(&remote, &["rev-parse", "main"]).as_bytes()).unwrap();
+        let objects = objects_to_send(&repository, new, &[have]).unwrap();
+        // The new commit, the root tree, dir, dir/b.txt and the new a.txt
+        assert_eq!(objects.len(), 5);
+        let pack = write_pack(&repository, &objects).unwrap();
+        let mut index_pack = Command::new("git")
+            .args(["index-pack", "--stdin", "--fix-thin"])
+            .current_dir(&remote)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        index_pack.stdin.take().unwrap().write_all(&pack).unwrap();
+        assert!(index_pack.wait().unwrap().success());
+        git(
+            &remote,
+            &["update-ref", "refs/heads/main", &new.to_string()],
+        );
+        git(&remote, &["fsck", "--strict"]);
+        remove_dir_all(&root).unwrap();
+    }
+
+    // Serves the bare repositories in root over smart HTTP with git http-backend,
+    // accepting only the user u with the password s3cr3t.
+    fn serve_http(root: PathBuf) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                handle_http(stream.unwrap(), &root);
+            }
+        });
+        url
+    }
+
+    fn handle_http(mut stream: TcpStream, root: &Path) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).unwrap();
+        let mut parts = request_line.split_whitespace();
+        let method = String::from(parts.next().unwrap());
+        let target = parts.next().unwrap();
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let mut content_length = 0;
+        let mut content_type = String::new();
+        let mut authorized = false;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line.split_once(": ").unwrap();
+            match name.to_ascii_lowercase().as_str() {
+                "content-length" => content_length = value.parse().unwrap(),
+                "content-type" => content_type = String::from(value),
+                "authorization" => {
+                    authorized = value == format!("Basic {}", STANDARD.encode("u:s3cr3t"))
+                }
+                _ => {}
+            }
+        }
+        if !authorized {
+            stream
+                .write_all(b"HTTP/1.1 401 Unauthorized\r\nWWW-Authenticate: Basic realm=\"git\"\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            return;
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+        let mut backend = Command::new("git")
+            .arg("http-backend")
+            .env("GIT_PROJECT_ROOT", root)
+            .env("GIT_HTTP_EXPORT_ALL", "1")
+            .env("REMOTE_USER", "u")
+            .env("REQUEST_METHOD", &method)
+            .env("PATH_INFO", path)
+            .env("QUERY_STRING", query)
+            .env("CONTENT_TYPE", &content_type)
+            .env("CONTENT_LENGTH", content_length.to_string())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        backend.stdin.take().unwrap().write_all(&body).unwrap();
+        let output = backend.wait_with_output().unwrap().stdout;
+        let split = output.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let headers = String::from_utf8_lossy(&output[..split]);
+        let content = &output[(split + 4)..];
+        let mut status = String::from("200 OK");
+        let mut response = String::new();
+        for header in headers.split("\r\n") {
+            match header.strip_prefix("Status: ") {
+                Some(s) => status = String::from(s),
+                None => response.push_str(&format!("{header}\r\n")),
+            }
+        }
+        stream
+            .write_all(
+                format!(
+                    "HTTP/1.1 {status}\r\n{response}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                    content.len()
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+        stream.write_all(content).unwrap();
+    }
+
+    #[test]
+    fn pushes_over_smart_http() {
+        let root = scratch("http");
+        let (local, remote) = set_up(&root);
+        let repository = gix::open(&local).unwrap();
+        let new = head(&repository);
+        let url = format!("{}/remote.git", serve_http(root.clone()));
+
+        assert!(matches!(
+            push_commit(
+                &repository,
+                &url,
+                Some("u"),
+                Some("wrong"),
+                new,
+                "main",
+                false
+            ),
+            Err(SyncError::Authentication(_))
+        ));
+
+        assert!(push_commit(
+            &repository,
+            &url,
+            Some("u"),
+            Some("s3cr3t"),
+            new,
+            "main",
+            false
+        )
+        .is_ok());
+        assert_eq!(git(&remote, &["rev-parse", "main"]), new.to_string());
+        git(&remote, &["fsck", "--strict"]);
+
+        // Pushing the same commit again is a no-op.
+        assert!(push_commit(
+            &repository,
+            &url,
+            Some("u"),
+            Some("s3cr3t"),
+            new,
+            "main",
+            false
+        )
+        .is_ok());
+
+        // A new branch in the remote repository
+        assert!(push_commit(
+            &repository,
+            &url,
+            Some("u"),
+            Some("s3cr3t"),
+            new,
+            "sync",
+            true
+        )
+        .is_ok());
+        assert_eq!(git(&remote, &["rev-parse", "sync"]), new.to_string());
+
+        // The remote branch moves on, so the push is no longer a fast forward.
+        let other = root.join("other");
+        git(&root, &["clone", "-q", "remote.git", "other"]);
+        write(other.join("c.txt"), "c\n").unwrap();
+        git(&other, &["add", "-A"]);
+        git(&other, &["commit", "-q", "-m", "third"]);
+        git(&other, &["push", "-q", "origin", "main"]);
+        assert!(matches!(
+            push_commit(
+                &repository,
+                &url,
+                Some("u"),
+                Some("s3cr3t"),
+                new,
+                "main",
+                false
+            ),
+            Err(SyncError::PushRejected(_))
+        ));
+        assert!(push_commit(
+            &repository,
+            &url,
+            Some("u"),
+            Some("s3cr3t"),
+            new,
+            "main",
+            true
+        )
+        .is_ok());
+        assert_eq!(git(&remote, &["rev-parse", "main"]), new.to_string());
+
+        remove_dir_all(&root).unwrap();
+    }
+}
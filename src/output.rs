@@ -0,0 +1,90 @@
+use std::cell::RefCell;
+use std::env::var;
+use std::sync::Mutex;
+
+// Every message printed by yellow-chameleon, including the output of git,
+// goes through this module so that credentials are never printed.
+static SECRETS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+const MASK: &str = "***";
+
+thread_local! {
+    // Set while a destination from the manifest is synced, so that the output
+    // of destinations synced in parallel can be told apart.
+    static PREFIX: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+// Masking a username of one or two letters would garble every message
+// while protecting nothing. Other secrets are masked whatever their length.
+const MIN_USERNAME_LENGTH: usize = 3;
+
+fn is_github_actions() -> bool {
+    matches!(var("GITHUB_ACTIONS").as_deref(), Ok("true"))
+}
+
+// Multi-line values, such as private keys, are masked line by line,
+// which is also how GitHub Actions masks them.
+pub(crate) fn add_secret(value: &str) {
+    let mut secrets = match SECRETS.lock() {
+        Err(poisoned) => poisoned.into_inner(),
+        Ok(s) => s,
+    };
+    for line in value.lines() {
+        let line = line.trim();
+        if line.is_empty() || secrets.iter().any(|s| s == line) {
+            continue;
+        }
+        if is_github_actions() {
+            println!("::add-mask::{line}");
+        }
+        secrets.push(String::from(line));
+    }
+    // Longer secrets are replaced first, in case one contains another.
+    secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+}
+
+pub(crate) fn add_username(value: &str) {
+    if value.trim().len() >= MIN_USERNAME_LENGTH {
+        add_secret(value);
+    }
+}
+
+pub(crate) fn mask_secrets(text: &str) -> String {
+    let secrets = match SECRETS.lock() {
+        Err(poisoned) => poisoned.into_inner(),
+        Ok(s) => s,
+    };
+    let mut masked = String::from(text);
+    for secret in secrets.iter() {
+        masked = masked.replace(secret.as_str(), MASK);
+    }
+    masked
+}
+
+pub(crate) fn set_output_prefix(prefix: &str) {
+    PREFIX.with(|cell| *cell.borrow_mut() = String::from(prefix));
+}
+
+// Every line is prefixed, and the text is printed at once,
+// so that lines from other threads cannot be interleaved within it.
+fn prefix_lines(text: &str) -> String {
+    PREFIX.with(|cell| {
+        let prefix = cell.borrow();
+        if prefix.is_empty() {
+            return String::from(text);
+        }
+        let lines: Vec<String> = text
+            .split('\n')
+            .map(|line| format!("{prefix}{line}"))
+            .collect();
+        lines.join("\n")
+    })
+}
+
+pub(crate) fn print_line(text: &str) {
+    println!("{}", prefix_lines(&mask_secrets(text)));
+}
+
+pub(crate) fn print_error(text: &str) {
+    eprintln!("{}", prefix_lines(&mask_secrets(text)));
+}
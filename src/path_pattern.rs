@@ -0,0 +1,285 @@
+use crate::sanitize_path::sanitize;
+
+pub(crate) struct PathPattern {
+    segments: Vec<String>,
+    anchored: bool,
+    pub negated: bool,
+    // The entry as written in the configuration file
+    pub text: String,
+}
+
+fn has_wildcard(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+// A pattern made of a single segment containing a wildcard (such as *.pem)
+// may match a file or directory at any depth in the tree.
+// Any other pattern is matched against the full path starting at the root,
+// which keeps plain paths behaving exactly as they did before globs were supported.
+//
+// A pattern starting with ! re-includes paths matched by an earlier pattern.
+pub(crate) fn parse_pattern(entry: String) -> Option<PathPattern> {
+    let text = entry.clone();
+    let (negated, body) = match entry.strip_prefix('!') {
+        None => (false, entry),
+        Some(rest) => (true, String::from(rest)),
+    };
+    let sanitized = match sanitize(body) {
+        None => return None,
+        Some(s) => s,
+    };
+    let segments: Vec<String> = sanitized.split('/').map(String::from).collect();
+    let anchored = segments.len() > 1 || !has_wildcard(&sanitized);
+    Some(PathPattern {
+        segments: segments,
+        anchored: anchored,
+        negated: negated,
+        text: text,
+    })
+}
+
+pub(crate) fn literal_pattern(path: &str) -> PathPattern {
+    PathPattern {
+        segments: path.split('/').map(String::from).collect(),
+        anchored: true,
+        negated: false,
+        text: String::from(path),
+    }
+}
+
+fn match_class(class: &[char], c: char) -> Option<(bool, usize)> {
+    // Returns whether c is in the class and the number of chars consumed
+    // from the class (not including the opening bracket).
+    let mut index = 0;
+    let negated = match class.first() {
+        Some('!') | Some('^') => {
+            index += 1;
+            true
+        }
+        _ => false,
+    };
+    let mut found = false;
+    let mut first = true;
+    while index < class.len() {
+        let start = class[index];
+        if start == ']' && !first {
+            return Some((found != negated, index + 1));
+        }
+        first = false;
+        if index + 2 < class.len() && class[index + 1] == '-' && class[index + 2] != ']' {
+            if start <= c && c <= class[index + 2] {
+                found = true;
+            }
+            index += 3;
+        } else {
+            if start == c {
+                found = true;
+            }
+            index += 1;
+        }
+    }
+    None
+}
+
+fn match_segment(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => {
+            for skip in 0..(name.len() + 1) {
+                if match_segment(&pattern[1..], &name[skip..]) {
+                    return true;
+                }
+            }
+            false
+        }
+        Some('?') => !name.is_empty() && match_segment(&pattern[1..], &name[1..]),
+        Some('[') => {
+            if name.is_empty() {
+                return false;
+            }
+            match match_class(&pattern[1..], name[0]) {
+                // An unterminated class is treated as a literal bracket.
+                None => name[0] == '[' && match_segment(&pattern[1..], &name[1..]),
+                Some((is_match, consumed)) => {
+                    is_match && match_segment(&pattern[(consumed + 1)..], &name[1..])
+                }
+            }
+        }
+        Some('\\') if pattern.len() > 1 => {
+            !name.is_empty() && name[0] == pattern[1] && match_segment(&pattern[2..], &name[1..])
+        }
+        Some(c) => !name.is_empty() && name[0] == *c && match_segment(&pattern[1..], &name[1..]),
+    }
+}
+
+fn match_segments(pattern: &[String], path: &[&str]) -> bool {
+    match pattern.first() {
+        None => path.is_empty(),
+        Some(first) => {
+            if first == "**" {
+                for skip in 0..(path.len() + 1) {
+                    if match_segments(&pattern[1..], &path[skip..]) {
+                        return true;
+                    }
+                }
+                return false;
+            }
+            if path.is_empty() {
+                return false;
+            }
+            let pattern_chars: Vec<char> = first.chars().collect();
+            let name_chars: Vec<char> = path[0].chars().collect();
+            match_segment(&pattern_chars, &name_chars) && match_segments(&pattern[1..], &path[1..])
+        }
+    }
+}
+
+// A pattern matches a path if it matches the path itself or any of its parent directories.
+pub(crate) fn pattern_matches(pattern: &PathPattern, path: &str) -> bool {
+    let path_segments: Vec<&str> = path.split('/').collect();
+    if pattern.anchored {
+        for length in 1..(path_segments.len() + 1) {
+            if match_segments(&pattern.segments, &path_segments[..length]) {
+                return true;
+            }
+        }
+        false
+    } else {
+        path_segments
+            .iter()
+            .any(|name| match_segments(&pattern.segments, &[name]))
+    }
+}
+
+pub(crate) enum PatternListMatch {
+    Unmatched,
+    Matched,
+    // The path is matched, but a later negated pattern may re-include
+    // something inside of it, so it has to be examined entry by entry.
+    MatchedWithExceptions,
+}
+
+// Patterns are evaluated in order and the last pattern matching a path decides
+// whether the path is matched, in the same way as a .gitignore file.
+pub(crate) fn deciding_pattern_index(pattern_list: &[PathPattern], path: &str) -> Option<usize> {
+    pattern_list
+        .iter()
+        .rposition(|pattern| pattern_matches(pattern, path))
+}
+
+pub(crate) fn match_pattern_list(pattern_list: &[PathPattern], path: &str) -> PatternListMatch {
+    let deciding_index = match deciding_pattern_index(pattern_list, path) {
+        None => return PatternListMatch::Unmatched,
+        Some(index) => index,
+    };
+    if pattern_list[deciding_index].negated {
+        return PatternListMatch::Unmatched;
+    }
+    if pattern_list[(deciding_index + 1)..]
+        .iter()
+        .any(|pattern| pattern.negated)
+    {
+        PatternListMatch::MatchedWithExceptions
+    } else {
+        PatternListMatch::Matched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(entry: &str) -> PathPattern {
+        parse_pattern(String::from(entry)).unwrap()
+    }
+
+    fn pattern_list(entries: &[&str]) -> Vec<PathPattern> {
+        entries.iter().map(|entry| pattern(entry)).collect()
+    }
+
+    #[test]
+    fn plain_path_matches_itself_and_its_contents() {
+        let p = pattern("docs/internal");
+        assert!(pattern_matches(&p, "docs/internal"));
+        assert!(pattern_matches(&p, "docs/internal/a.md"));
+        assert!(!pattern_matches(&p, "docs/internal-notes.md"));
+        assert!(!pattern_matches(&p, "other/docs/internal"));
+    }
+
+    #[test]
+    fn single_segment_glob_matches_at_any_depth() {
+        let p = pattern("*.pem");
+        assert!(pattern_matches(&p, "key.pem"));
+        assert!(pattern_matches(&p, "certs/deep/key.pem"));
+        assert!(!pattern_matches(&p, "key.pem.txt"));
+    }
+
+    #[test]
+    fn multi_segment_glob_is_anchored() {
+        let p = pattern("src/*.rs");
+        assert!(pattern_matches(&p, "src/main.rs"));
+        assert!(!pattern_matches(&p, "lib/src/main.rs"));
+        assert!(!pattern_matches(&p, "src/nested/main.rs"));
+    }
+
+    #[test]
+    fn double_star_matches_any_number_of_directories() {
+        let p = pattern("docs/**/draft.md");
+        assert!(pattern_matches(&p, "docs/draft.md"));
+        assert!(pattern_matches(&p, "docs/a/b/draft.md"));
+        assert!(!pattern_matches(&p, "src/draft.md"));
+    }
+
+    #[test]
+    fn question_mark_and_classes() {
+        assert!(pattern_matches(&pattern("v?.txt"), "v1.txt"));
+        assert!(!pattern_matches(&pattern("v?.txt"), "v10.txt"));
+        assert!(pattern_matches(&pattern("[a-c].txt"), "b.txt"));
+        assert!(!pattern_matches(&pattern("[!a-c].txt"), "b.txt"));
+        assert!(pattern_matches(&pattern("[!a-c].txt"), "d.txt"));
+    }
+
+    #[test]
+    fn negated_entry_is_parsed() {
+        let p = pattern("!internal/public-api");
+        assert!(p.negated);
+        assert_eq!(p.text, "!internal/public-api");
+        assert!(pattern_matches(&p, "internal/public-api/a.rs"));
+    }
+
+    #[test]
+    fn last_matching_pattern_decides() {
+        let list = pattern_list(&["internal", "!internal/public-api"]);
+        assert!(matches!(
+            match_pattern_list(&list, "internal/public-api/a.rs"),
+            PatternListMatch::Unmatched
+        ));
+        assert!(matches!(
+            match_pattern_list(&list, "src/main.rs"),
+            PatternListMatch::Unmatched
+        ));
+        let list = pattern_list(&["!internal/public-api", "internal"]);
+        assert!(matches!(
+            match_pattern_list(&list, "internal/public-api/a.rs"),
+            PatternListMatch::Matched
+        ));
+    }
+
+    #[test]
+    fn directory_with_later_negation_has_exceptions() {
+        let list = pattern_list(&["internal", "!internal/public-api"]);
+        assert!(matches!(
+            match_pattern_list(&list, "internal"),
+            PatternListMatch::MatchedWithExceptions
+        ));
+    }
+
+    #[test]
+    fn later_pattern_overrides_negation() {
+        let list = pattern_list(&["internal", "!internal/public-api", "internal/public-api"]);
+        assert!(matches!(
+            match_pattern_list(&list, "internal/public-api/a.rs"),
+            PatternListMatch::Matched
+        ));
+    }
+}
@@ -0,0 +1,17 @@
+use std::fs::read;
+
+// Returns None if the file appears to be binary.
+// A file is considered binary if it contains a NUL byte or is not valid UTF-8.
+pub(crate) fn read_text_file(filepath: &str) -> Result<Option<String>, String> {
+    let bytes = match read(filepath) {
+        Err(_) => return Err(format!("Unable to read {filepath}")),
+        Ok(b) => b,
+    };
+    if bytes.contains(&0) {
+        return Ok(None);
+    }
+    match String::from_utf8(bytes) {
+        Err(_) => Ok(None),
+        Ok(s) => Ok(Some(s)),
+    }
+}
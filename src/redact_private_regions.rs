@@ -0,0 +1,180 @@
+use std::fs::{read, write};
+
+use crate::commands::ls_files;
+
+const BEGIN_MARKER: &str = "yellow-chameleon:private-begin";
+const END_MARKER: &str = "yellow-chameleon:private-end";
+
+enum Marker {
+    Begin,
+    End,
+}
+
+// The markers are ASCII, so they are found in the raw bytes of a line
+// and files in any ASCII-compatible encoding are redacted.
+fn strip_comment(line: &[u8]) -> Option<&[u8]> {
+    let trimmed = line.trim_ascii();
+    if let Some(rest) = trimmed.strip_prefix(b"<!--") {
+        return rest.strip_suffix(b"-->");
+    }
+    if let Some(rest) = trimmed.strip_prefix(b"/*") {
+        return rest.strip_suffix(b"*/");
+    }
+    if let Some(rest) = trimmed.strip_prefix(b"//") {
+        return Some(rest);
+    }
+    trimmed.strip_prefix(b"#")
+}
+
+fn find_marker(line: &[u8]) -> Option<Marker> {
+    match strip_comment(line) {
+        None => None,
+        Some(comment) => {
+            let comment = comment.trim_ascii();
+            if comment == BEGIN_MARKER.as_bytes() {
+                Some(Marker::Begin)
+            } else if comment == END_MARKER.as_bytes() {
+                Some(Marker::End)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+// Returns None if the file does not contain any private regions.
+fn redact_text(text: &[u8], filepath_on_error: &str) -> Result<Option<Vec<u8>>, String> {
+    let mut output: Vec<u8> = Vec::with_capacity(text.len());
+    let mut open_region_line: Option<usize> = None;
+    let mut found_region = false;
+    for (index, line) in text.split_inclusive(|byte| *byte == b'\n').enumerate() {
+        let line_number = index + 1;
+        match find_marker(line) {
+            Some(Marker::Begin) => {
+                if let Some(open_line) = open_region_line {
+                    return Err(format!(
+                        "{filepath_on_error}:{line_number}: found {BEGIN_MARKER} inside the private region started on line {open_line}"
+                    ));
+                }
+                open_region_line = Some(line_number);
+                found_region = true;
+            }
+            Some(Marker::End) => {
+                if open_region_line.is_none() {
+                    return Err(format!(
+                        "{filepath_on_error}:{line_number}: found {END_MARKER} without a matching {BEGIN_MARKER}"
+                    ));
+                }
+                open_region_line = None;
+            }
+            None => {
+                if open_region_line.is_none() {
+                    output.extend_from_slice(line);
+                }
+            }
+        }
+    }
+    if let Some(open_line) = open_region_line {
+        return Err(format!(
+            "{filepath_on_error}:{open_line}: found {BEGIN_MARKER} without a matching {END_MARKER}"
+        ));
+    }
+    if found_region {
+        Ok(Some(output))
+    } else {
+        Ok(None)
+    }
+}
+
+pub(crate) fn redact_private_regions(source_path: &str) -> Result<(), String> {
+    let files = match ls_files(source_path) {
+        Err(e) => return Err(e),
+        Ok(v) => v,
+    };
+    for relative_path in files {
+        let full_path = format!("{source_path}/{relative_path}");
+        let text = match read(&full_path) {
+            Err(_) => return Err(format!("Unable to read {full_path}")),
+            Ok(b) => b,
+        };
+        // Binary files are never modified.
+        if text.contains(&0) {
+            continue;
+        }
+        let redacted = match redact_text(&text, &relative_path) {
+            Err(e) => return Err(e),
+            Ok(None) => continue,
+            Ok(Some(r)) => r,
+        };
+        match write(&full_path, redacted) {
+            Err(_) => return Err(format!("Unable to write {full_path}")),
+            Ok(_) => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_without_markers_is_unchanged() {
+        assert_eq!(redact_text(b"a\nb\n", "f.rs").unwrap(), None);
+    }
+
+    #[test]
+    fn removes_markers_and_region() {
+        let text = "keep 1\n// yellow-chameleon:private-begin\nsecret\n// yellow-chameleon:private-end\nkeep 2\n";
+        assert_eq!(
+            redact_text(text.as_bytes(), "f.rs").unwrap(),
+            Some(b"keep 1\nkeep 2\n".to_vec())
+        );
+    }
+
+    #[test]
+    fn accepts_every_comment_style() {
+        let text = "# yellow-chameleon:private-begin\na\n  /* yellow-chameleon:private-end */\n<!-- yellow-chameleon:private-begin -->\nb\n<!--yellow-chameleon:private-end-->\nc";
+        assert_eq!(
+            redact_text(text.as_bytes(), "f.md").unwrap(),
+            Some(b"c".to_vec())
+        );
+    }
+
+    #[test]
+    fn marker_must_be_alone_on_its_line() {
+        let text = "let x = 1; // yellow-chameleon:private-begin\n";
+        assert_eq!(redact_text(text.as_bytes(), "f.rs").unwrap(), None);
+    }
+
+    #[test]
+    fn unmatched_markers_name_file_and_line() {
+        assert_eq!(
+            redact_text(b"a\n// yellow-chameleon:private-begin\nb\n", "src/f.rs").unwrap_err(),
+            "src/f.rs:2: found yellow-chameleon:private-begin without a matching yellow-chameleon:private-end"
+        );
+        assert_eq!(
+            redact_text(b"// yellow-chameleon:private-end\n", "src/f.rs").unwrap_err(),
+            "src/f.rs:1: found yellow-chameleon:private-end without a matching yellow-chameleon:private-begin"
+        );
+    }
+
+    #[test]
+    fn nested_begin_is_an_error() {
+        let text = "# yellow-chameleon:private-begin\n# yellow-chameleon:private-begin\n";
+        assert_eq!(
+            redact_text(text.as_bytes(), "f.py").unwrap_err(),
+            "f.py:2: found yellow-chameleon:private-begin inside the private region started on line 1"
+        );
+    }
+
+    #[test]
+    fn redacts_text_that_is_not_utf8() {
+        // Latin-1, where \xe9 is é
+        let text = b"caf\xe9\n# yellow-chameleon:private-begin\nmot de passe: \xe9t\xe9\n# yellow-chameleon:private-end\nfin\n";
+        assert_eq!(
+            redact_text(text, "notes.txt").unwrap(),
+            Some(b"caf\xe9\nfin\n".to_vec())
+        );
+    }
+}
@@ -0,0 +1,248 @@
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::env::var;
+use std::fs::write;
+use std::time::Instant;
+
+use json::JsonValue;
+
+use crate::commands::{ChangeKind, GitDiffResult};
+use crate::get_environment_configuration::EnvironmentConfiguration;
+use crate::git_backend::GitBackendKind;
+use crate::output::{mask_secrets, print_line};
+use crate::path_pattern::PathPattern;
+use crate::sync_error::SyncError;
+
+// Increased whenever a field is removed or changes meaning.
+const REPORT_VERSION: u32 = 1;
+
+thread_local! {
+    // Filled in as the sync runs, and written out once it has finished.
+    static REPORT: RefCell<JsonValue> = RefCell::new(new_report());
+}
+
+fn new_report() -> JsonValue {
+    let mut report = JsonValue::new_object();
+    report["version"] = REPORT_VERSION.into();
+    report["configuration"] = JsonValue::Null;
+    report["ignore_entries"] = JsonValue::new_array();
+    report["ignore_entries_matched"] = JsonValue::new_array();
+    report["lock_entries"] = JsonValue::new_array();
+    report["lock_entries_matched"] = JsonValue::new_array();
+    report["destination_path"] = JsonValue::Null;
+    report["commit_sha"] = JsonValue::Null;
+    report["push"] = JsonValue::Null;
+    report["pull_request_url"] = JsonValue::Null;
+    report["timings"] = JsonValue::new_array();
+    report
+}
+
+// Returns the report of the finished sync, and starts a new one
+// for the next destination synced by this thread.
+pub(crate) fn take_report() -> JsonValue {
+    REPORT.with(|cell| cell.replace(new_report()))
+}
+
+fn update_report(update: impl FnOnce(&mut JsonValue)) {
+    REPORT.with(|cell| update(&mut cell.borrow_mut()));
+}
+
+fn optional_string(value: &Option<String>) -> JsonValue {
+    match value {
+        None => JsonValue::Null,
+        Some(s) => mask_secrets(s).into(),
+    }
+}
+
+// Secrets are only reported as being configured.
+fn optional_secret(value: &Option<String>) -> JsonValue {
+    match value {
+        None => JsonValue::Null,
+        Some(_) => "***".into(),
+    }
+}
+
+// Only destinations listed in the manifest have a name.
+pub(crate) fn report_destination_name(name: &str) {
+    update_report(|report| report["name"] = name.into());
+}
+
+pub(crate) fn report_configuration(environment_configuration: &EnvironmentConfiguration) {
+    let mut configuration = JsonValue::new_object();
+    configuration["source_path"] = environment_configuration.source_path.as_str().into();
+    configuration["destination_repository"] =
+        mask_secrets(&environment_configuration.dest_repo).into();
+    configuration["destination_url"] =
+        mask_secrets(&environment_configuration.dest_repo_url).into();
+    configuration["destination_host"] = mask_secrets(&environment_configuration.dest_host).into();
+    configuration["destination_branch"] = optional_string(&environment_configuration.dest_branch);
+    configuration["destination_pat"] = optional_secret(&environment_configuration.dest_pat);
+    configuration["destination_pat_username"] =
+        optional_secret(&environment_configuration.dest_pat_user);
+    configuration["destination_ssh_key"] = optional_secret(&environment_configuration.dest_ssh_key);
+    configuration["destination_known_hosts"] =
+        optional_string(&environment_configuration.dest_known_hosts);
+    configuration["git_name"] = environment_configuration.git_name.as_str().into();
+    configuration["git_email"] = environment_configuration.git_email.as_str().into();
+    configuration["git_backend"] = match environment_configuration.git_backend {
+        GitBackendKind::Cli => "cli",
+        GitBackendKind::Native => "native",
+    }
+    .into();
+    configuration["dry_run"] = environment_configuration.dry_run.into();
+    configuration["preserve_history"] = environment_configuration.preserve_history.into();
+    configuration["commit_message"] = optional_string(&environment_configuration.commit_message);
+    configuration["include_source_subject"] =
+        environment_configuration.include_source_subject.into();
+    configuration["source_ref"] = optional_string(&environment_configuration.source_ref);
+    configuration["pull_request"] = environment_configuration.pull_request.into();
+    configuration["pull_request_title"] =
+        mask_secrets(&environment_configuration.pull_request_title).into();
+    configuration["api_url"] = mask_secrets(&environment_configuration.api_url).into();
+    configuration["max_parallel"] = environment_configuration.max_parallel.into();
+    update_report(|report| report["configuration"] = configuration);
+}
+
+// key is either ignore_entries or lock_entries.
+// matched holds indices into pattern_list.
+pub(crate) fn report_matched_entries(
+    key: &str,
+    pattern_list: &[PathPattern],
+    matched: &BTreeSet<usize>,
+) {
+    let matched_key = format!("{key}_matched");
+    update_report(|report| {
+        report[key] = JsonValue::new_array();
+        for pattern in pattern_list.iter() {
+            let _ = report[key].push(pattern.text.as_str());
+        }
+        for index in matched.iter() {
+            let text = pattern_list[*index].text.as_str();
+            if !report[matched_key.as_str()]
+                .members()
+                .any(|entry| entry == text)
+            {
+                let _ = report[matched_key.as_str()].push(text);
+            }
+        }
+    });
+}
+
+pub(crate) fn report_destination_path(path: &str) {
+    update_report(|report| report["destination_path"] = path.into());
+}
+
+// A phase that runs more than once, such as once per mirrored commit,
+// is reported with its total duration.
+pub(crate) fn report_phase(phase: &str, started: Instant) {
+    let milliseconds = started.elapsed().as_millis() as u64;
+    update_report(|report| {
+        for entry in report["timings"].members_mut() {
+            if entry["phase"] == phase {
+                let total = entry["milliseconds"].as_u64().unwrap_or(0) + milliseconds;
+                entry["milliseconds"] = total.into();
+                return;
+            }
+        }
+        let mut entry = JsonValue::new_object();
+        entry["phase"] = phase.into();
+        entry["milliseconds"] = milliseconds.into();
+        let _ = report["timings"].push(entry);
+    });
+}
+
+pub(crate) fn report_commit(sha: &str) {
+    update_report(|report| report["commit_sha"] = sha.into());
+}
+
+pub(crate) fn report_push(branch: &str, force: bool, succeeded: bool) {
+    let mut push = JsonValue::new_object();
+    push["branch"] = branch.into();
+    push["force"] = force.into();
+    push["succeeded"] = succeeded.into();
+    update_report(|report| report["push"] = push);
+}
+
+pub(crate) fn report_pull_request(url: &str) {
+    update_report(|report| report["pull_request_url"] = url.into());
+}
+
+fn change_kind_name(kind: &ChangeKind) -> &'static str {
+    match kind {
+        ChangeKind::Added => "added",
+        ChangeKind::Modified => "modified",
+        ChangeKind::Deleted => "deleted",
+        ChangeKind::Renamed => "renamed",
+    }
+}
+
+fn optional_count(count: Option<usize>) -> JsonValue {
+    match count {
+        None => JsonValue::Null,
+        Some(c) => c.into(),
+    }
+}
+
+pub(crate) fn report_outcome(outcome: &Result<(GitDiffResult, bool), SyncError>) {
+    update_report(|report| match outcome {
+        Err(e) => {
+            report["result"] = "error".into();
+            let mut error = JsonValue::new_object();
+            error["kind"] = e.kind().into();
+            error["exit_code"] = e.exit_code().into();
+            error["message"] = mask_secrets(e.message()).into();
+            report["error"] = error;
+        }
+        Ok((GitDiffResult::NoChanges, _)) => {
+            report["result"] = "no_changes".into();
+            report["changes"] = JsonValue::new_array();
+        }
+        Ok((GitDiffResult::Changes(changes), dry_run)) => {
+            report["result"] = if *dry_run { "dry_run" } else { "changes" }.into();
+            let mut list = JsonValue::new_array();
+            for change in changes.iter() {
+                let mut entry = JsonValue::new_object();
+                entry["kind"] = change_kind_name(&change.kind).into();
+                entry["path"] = change.path.as_str().into();
+                entry["previous_path"] = optional_string(&change.previous_path);
+                entry["lines_added"] = optional_count(change.lines_added);
+                entry["lines_deleted"] = optional_count(change.lines_deleted);
+                let _ = list.push(entry);
+            }
+            report["changes"] = list;
+        }
+    });
+}
+
+// The report is written to the file named by report-path, and printed
+// if report-stdout is true. These are read here rather than with the rest of
+// the configuration, so that a report is written even if the configuration is invalid.
+// When a manifest is used, the reports of all destinations are written together.
+pub(crate) fn write_report(reports: &[JsonValue], is_manifest: bool) -> Result<(), String> {
+    let report = if is_manifest {
+        let mut combined = JsonValue::new_object();
+        combined["version"] = REPORT_VERSION.into();
+        combined["destinations"] = JsonValue::Array(reports.to_vec());
+        combined
+    } else {
+        match reports.first() {
+            None => JsonValue::Null,
+            Some(r) => r.clone(),
+        }
+    };
+    let text = mask_secrets(&report.pretty(2));
+    if let Ok(path) = var("CAM_REPORT_PATH") {
+        if !path.is_empty() {
+            match write(&path, format!("{text}\n")) {
+                Err(e) => return Err(format!("Unable to write report to {path}: {e}")),
+                Ok(_) => {}
+            }
+        }
+    }
+    if let Ok(value) = var("CAM_REPORT_STDOUT") {
+        if value == "true" {
+            print_line(&text);
+        }
+    }
+    Ok(())
+}
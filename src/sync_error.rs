@@ -0,0 +1,99 @@
+// The category of an error decides the exit code of yellow-chameleon,
+// so that wrapper workflows can tell retryable failures from permanent ones.
+// The exit codes are documented in the README and must not change.
+pub(crate) enum SyncError {
+    // Any error that does not fit a more specific category
+    Internal(String),
+    // An input or configuration file is invalid
+    Configuration(String),
+    // The source path does not exist
+    SourceMissing(String),
+    // The destination repository could not be cloned or checked out
+    Clone(String),
+    // The destination repository rejected the credentials
+    Authentication(String),
+    // An ignore, include, lock, redaction, or replace rule could not be applied
+    Transformation(String),
+    // The secret scan found a potential secret
+    SecretDetected(String),
+    // The push was rejected because the destination branch has moved
+    PushRejected(String),
+    // The push failed for any other reason
+    Push(String),
+    // The pull request could not be created or updated
+    PullRequest(String),
+}
+
+impl SyncError {
+    pub(crate) fn message(&self) -> &str {
+        match self {
+            SyncError::Internal(m)
+            | SyncError::Configuration(m)
+            | SyncError::SourceMissing(m)
+            | SyncError::Clone(m)
+            | SyncError::Authentication(m)
+            | SyncError::Transformation(m)
+            | SyncError::SecretDetected(m)
+            | SyncError::PushRejected(m)
+            | SyncError::Push(m)
+            | SyncError::PullRequest(m) => m,
+        }
+    }
+
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            SyncError::Internal(_) => "internal",
+            SyncError::Configuration(_) => "configuration",
+            SyncError::SourceMissing(_) => "source_missing",
+            SyncError::Clone(_) => "clone",
+            SyncError::Authentication(_) => "authentication",
+            SyncError::Transformation(_) => "transformation",
+            SyncError::SecretDetected(_) => "secret_detected",
+            SyncError::PushRejected(_) => "push_rejected",
+            SyncError::Push(_) => "push",
+            SyncError::PullRequest(_) => "pull_request",
+        }
+    }
+
+    pub(crate) fn exit_code(&self) -> u8 {
+        match self {
+            SyncError::Internal(_) => 1,
+            SyncError::Configuration(_) => 2,
+            SyncError::SourceMissing(_) => 3,
+            SyncError::Clone(_) => 4,
+            SyncError::Authentication(_) => 5,
+            SyncError::Transformation(_) => 6,
+            SyncError::SecretDetected(_) => 7,
+            SyncError::PushRejected(_) => 8,
+            SyncError::Push(_) => 9,
+            SyncError::PullRequest(_) => 10,
+        }
+    }
+}
+
+const AUTHENTICATION_FAILURES: [&str; 8] = [
+    "Authentication failed",
+    "could not read Username",
+    "could not read Password",
+    "terminal prompts disabled",
+    "Permission denied (publickey",
+    "Host key verification failed",
+    "The requested URL returned error: 401",
+    "The requested URL returned error: 403",
+];
+
+const PUSH_REJECTIONS: [&str; 3] = ["non-fast-forward", "(fetch first)", "(stale info)"];
+
+pub(crate) fn is_authentication_failure(git_stderr: &str) -> bool {
+    AUTHENTICATION_FAILURES
+        .iter()
+        .any(|pattern| git_stderr.contains(pattern))
+}
+
+// Only rejections that a later retry can resolve are included,
+// so a push blocked by a protected branch rule is not one of them.
+pub(crate) fn is_push_rejection(git_stderr: &str) -> bool {
+    PUSH_REJECTIONS
+        .iter()
+        .any(|pattern| git_stderr.contains(pattern))
+}
@@ -1,30 +1,222 @@
+use std::collections::BTreeSet;
 use std::path::Path;
 
-use crate::commands::{
-    git_add_all, git_commit, git_diff, git_push, ls, mkdir, mkdir_all, mv, rm, GitDiffResult,
+use chrono::offset::Utc;
+
+use crate::apply_replace_rules::apply_replace_rules;
+use crate::commands::{cp, ls, mkdir, mkdir_all, mv, rm, ChangeKind, GitDiffResult, SourceCommit};
+use crate::commit_message::{render_commit_message, CommitMessageContext};
+use crate::get_environment_configuration::{
+    default_git_identity, in_work_path, EnvironmentConfiguration,
+};
+use crate::get_json_configuration::{
+    get_destination_configuration, DestinationConfiguration, SourceConfiguration,
+};
+use crate::git_backend::GitBackend;
+use crate::output::print_line;
+use crate::path_pattern::{
+    deciding_pattern_index, match_pattern_list, PathPattern, PatternListMatch,
+};
+use crate::redact_private_regions::redact_private_regions;
+use crate::run_report::{
+    report_commit, report_destination_path, report_matched_entries, report_push,
 };
+use crate::scan_for_secrets::{scan_for_secrets, SecretScanConfiguration};
+use crate::sync_error::SyncError;
 
-pub(crate) fn apply_ignore_list(
-    source_path: &String,
-    ignore_list: Vec<String>,
+fn join_relative_path(relative_directory: &str, filename: String) -> String {
+    if relative_directory.is_empty() {
+        filename
+    } else {
+        format!("{relative_directory}/{filename}")
+    }
+}
+
+fn is_real_directory(full_path: &str) -> bool {
+    let path = Path::new(full_path);
+    !(path.is_symlink()) && path.is_dir()
+}
+
+// Records the entry that decided that path is matched.
+fn record_match(matched: &mut BTreeSet<usize>, pattern_list: &[PathPattern], path: &str) {
+    match deciding_pattern_index(pattern_list, path) {
+        None => {}
+        Some(index) => {
+            matched.insert(index);
+        }
+    }
+}
+
+fn remove_matching_paths(
+    root: &str,
+    relative_directory: &str,
+    pattern_list: &[PathPattern],
+    matched: &mut BTreeSet<usize>,
 ) -> Result<(), String> {
-    for filename in ignore_list {
-        let full_path = format!("{source_path}/{filename}");
-        match rm(&full_path) {
-            Err(e) => return Err(e),
-            Ok(_) => {}
+    let filenames = match ls(&join_relative_path(root, String::from(relative_directory))) {
+        Err(e) => return Err(e),
+        Ok(v) => v,
+    };
+    for filename in filenames {
+        let relative_path = join_relative_path(relative_directory, filename);
+        let full_path = format!("{root}/{relative_path}");
+        let is_matched = match match_pattern_list(pattern_list, &relative_path) {
+            PatternListMatch::Unmatched => false,
+            PatternListMatch::MatchedWithExceptions => true,
+            PatternListMatch::Matched => {
+                match rm(&full_path) {
+                    Err(e) => return Err(e),
+                    Ok(_) => {}
+                }
+                record_match(matched, pattern_list, &relative_path);
+                continue;
+            }
+        };
+        if is_real_directory(&full_path) {
+            match remove_matching_paths(root, &relative_path, pattern_list, matched) {
+                Err(e) => return Err(e),
+                Ok(_) => {}
+            }
+        } else if is_matched {
+            record_match(matched, pattern_list, &relative_path);
+            match rm(&full_path) {
+                Err(e) => return Err(e),
+                Ok(_) => {}
+            }
         }
     }
-    return Ok(());
+    Ok(())
+}
+
+// Copies every path under relative_directory of source_root that is not ignored,
+// and is included if there is an include list, to the same path under staging_root.
+// included is true once a parent directory is matched by the include list.
+fn copy_unignored_paths(
+    source_root: &str,
+    staging_root: &str,
+    relative_directory: &str,
+    source_configuration: &SourceConfiguration,
+    included: bool,
+    matched: &mut BTreeSet<usize>,
+) -> Result<(), String> {
+    let ignore_list = &source_configuration.ignore_list;
+    let filenames = match ls(&join_relative_path(
+        source_root,
+        String::from(relative_directory),
+    )) {
+        Err(e) => return Err(e),
+        Ok(v) => v,
+    };
+    for filename in filenames {
+        let relative_path = join_relative_path(relative_directory, filename);
+        let full_path = format!("{source_root}/{relative_path}");
+        let staging_path = format!("{staging_root}/{relative_path}");
+        let is_ignored = match match_pattern_list(ignore_list, &relative_path) {
+            PatternListMatch::Unmatched => false,
+            PatternListMatch::MatchedWithExceptions => true,
+            PatternListMatch::Matched => {
+                record_match(matched, ignore_list, &relative_path);
+                continue;
+            }
+        };
+        // A directory matched by the include list is included whole,
+        // while a file only needs to be matched with exceptions.
+        let include_match = match &source_configuration.include_list {
+            None => PatternListMatch::Matched,
+            Some(list) => match_pattern_list(list, &relative_path),
+        };
+        if is_real_directory(&full_path) {
+            match mkdir(&staging_path) {
+                Err(e) => return Err(e),
+                Ok(_) => {}
+            }
+            match copy_unignored_paths(
+                source_root,
+                staging_root,
+                &relative_path,
+                source_configuration,
+                included || matches!(include_match, PatternListMatch::Matched),
+                matched,
+            ) {
+                Err(e) => return Err(e),
+                Ok(_) => {}
+            }
+        } else if is_ignored {
+            record_match(matched, ignore_list, &relative_path);
+        } else if included || !matches!(include_match, PatternListMatch::Unmatched) {
+            match cp(&full_path, &staging_path) {
+                Err(e) => return Err(e),
+                Ok(_) => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+// Builds the outgoing tree in staging_path, without modifying source_path.
+pub(crate) fn apply_source_transformations(
+    source_path: &str,
+    staging_path: &str,
+    source_configuration: &SourceConfiguration,
+) -> Result<(), String> {
+    match rm(staging_path) {
+        Err(e) => return Err(e),
+        Ok(_) => {}
+    }
+    match mkdir_all(staging_path) {
+        Err(e) => return Err(e),
+        Ok(_) => {}
+    }
+    let mut matched = BTreeSet::new();
+    match copy_unignored_paths(
+        source_path,
+        staging_path,
+        "",
+        source_configuration,
+        false,
+        &mut matched,
+    ) {
+        Err(e) => return Err(e),
+        Ok(_) => {}
+    }
+    report_matched_entries(
+        "ignore_entries",
+        &source_configuration.ignore_list,
+        &matched,
+    );
+    match redact_private_regions(staging_path) {
+        Err(e) => return Err(e),
+        Ok(_) => {}
+    }
+    let substitution_counts =
+        match apply_replace_rules(staging_path, &source_configuration.replace_rules) {
+            Err(e) => return Err(e),
+            Ok(c) => c,
+        };
+    for (rule, count) in source_configuration
+        .replace_rules
+        .iter()
+        .zip(substitution_counts)
+    {
+        print_line(&format!(
+            "Replace rule {} made {count} substitutions",
+            rule.pattern.as_str()
+        ));
+    }
+    Ok(())
 }
 
 pub(crate) fn apply_dest_path(
     source_path: &String,
     dest_path: &Option<String>,
+    temp_path: &str,
 ) -> Result<(), String> {
     let temp_dir_inner_path = match dest_path {
         None => return Ok(()),
-        Some(relative_path) => format!("temp/{relative_path}"),
+        Some(relative_path) => {
+            report_destination_path(relative_path);
+            format!("{temp_path}/{relative_path}")
+        }
     };
     match mkdir_all(&temp_dir_inner_path) {
         Err(e) => return Err(e),
@@ -43,12 +235,12 @@ pub(crate) fn apply_dest_path(
             Ok(_) => {}
         }
     }
-    let files_to_move_2 = match ls("temp") {
+    let files_to_move_2 = match ls(temp_path) {
         Err(e) => return Err(e),
         Ok(v) => v,
     };
     for filename in files_to_move_2 {
-        match mv(format!("temp/{filename}").as_str(), &source_path) {
+        match mv(format!("{temp_path}/{filename}").as_str(), &source_path) {
             Err(e) => return Err(e),
             Ok(_) => {}
         }
@@ -56,66 +248,245 @@ pub(crate) fn apply_dest_path(
     Ok(())
 }
 
-pub(crate) fn apply_lock_list(source_path: &String, lock_list: &Vec<String>) -> Result<(), String> {
-    for lock_item in lock_list {
-        let mut lock_item_parts: Vec<&str> = lock_item.split("/").collect();
-        lock_item_parts.pop();
-        let mut dir_tree = source_path.clone();
-        for lock_item_part in lock_item_parts {
-            dir_tree.push('/');
-            dir_tree.push_str(lock_item_part);
-            let dir_tree_path = Path::new(&dir_tree);
-            if dir_tree_path.is_symlink() || !(dir_tree_path.is_dir()) {
-                match rm(&dir_tree) {
-                    Err(e) => return Err(e),
-                    Ok(_) => {}
-                }
-                match mkdir(&dir_tree) {
+fn move_locked_path(
+    source_path: &String,
+    destination_directory: &str,
+    lock_item: &str,
+) -> Result<(), String> {
+    let mut lock_item_parts: Vec<&str> = lock_item.split("/").collect();
+    lock_item_parts.pop();
+    let mut dir_tree = source_path.clone();
+    for lock_item_part in lock_item_parts {
+        dir_tree.push('/');
+        dir_tree.push_str(lock_item_part);
+        let dir_tree_path = Path::new(&dir_tree);
+        if dir_tree_path.is_symlink() || !(dir_tree_path.is_dir()) {
+            match rm(&dir_tree) {
+                Err(e) => return Err(e),
+                Ok(_) => {}
+            }
+            match mkdir(&dir_tree) {
+                Err(e) => return Err(e),
+                Ok(_) => {}
+            }
+        }
+    }
+    let write_to = format!("{source_path}/{lock_item}");
+    match rm(&write_to) {
+        Err(e) => return Err(e),
+        Ok(_) => {}
+    }
+    mv(&format!("{destination_directory}/{lock_item}"), &dir_tree)
+}
+
+fn move_locked_paths(
+    source_path: &String,
+    destination_directory: &str,
+    relative_directory: &str,
+    lock_list: &[PathPattern],
+    matched: &mut BTreeSet<usize>,
+) -> Result<(), String> {
+    let filenames = match ls(&join_relative_path(
+        destination_directory,
+        String::from(relative_directory),
+    )) {
+        Err(e) => return Err(e),
+        Ok(v) => v,
+    };
+    for filename in filenames {
+        let relative_path = join_relative_path(relative_directory, filename);
+        let is_locked = match match_pattern_list(lock_list, &relative_path) {
+            PatternListMatch::Unmatched => false,
+            PatternListMatch::MatchedWithExceptions => true,
+            PatternListMatch::Matched => {
+                match move_locked_path(source_path, destination_directory, &relative_path) {
                     Err(e) => return Err(e),
                     Ok(_) => {}
                 }
+                record_match(matched, lock_list, &relative_path);
+                continue;
+            }
+        };
+        if is_real_directory(&format!("{destination_directory}/{relative_path}")) {
+            match move_locked_paths(
+                source_path,
+                destination_directory,
+                &relative_path,
+                lock_list,
+                matched,
+            ) {
+                Err(e) => return Err(e),
+                Ok(_) => {}
+            }
+        } else if is_locked {
+            record_match(matched, lock_list, &relative_path);
+            match move_locked_path(source_path, destination_directory, &relative_path) {
+                Err(e) => return Err(e),
+                Ok(_) => {}
             }
-        }
-        let write_to = format!("{source_path}/{lock_item}");
-        match rm(&write_to) {
-            Err(e) => return Err(e),
-            Ok(_) => {}
-        }
-        let read_from = format!("destination/{lock_item}");
-        if !(Path::new(&read_from).exists()) {
-            continue;
-        }
-        match mv(&read_from, &dir_tree) {
-            Err(e) => return Err(e),
-            Ok(_) => {}
         }
     }
     Ok(())
 }
 
-pub(crate) fn git_upload(
+pub(crate) fn apply_lock_list(
     source_path: &String,
-    dest_repo_url: &str,
-    dest_pat: &str,
-) -> Result<GitDiffResult, String> {
-    match git_add_all(&source_path) {
+    destination_directory: &str,
+    lock_list: &[PathPattern],
+) -> Result<(), String> {
+    let mut matched = BTreeSet::new();
+    match remove_matching_paths(source_path, "", lock_list, &mut matched) {
+        Err(e) => return Err(e),
+        Ok(_) => {}
+    }
+    match move_locked_paths(
+        source_path,
+        destination_directory,
+        "",
+        lock_list,
+        &mut matched,
+    ) {
         Err(e) => return Err(e),
         Ok(_) => {}
+    }
+    report_matched_entries("lock_entries", lock_list, &matched);
+    Ok(())
+}
+
+// Must be called after the destination repository is cloned.
+pub(crate) fn apply_destination_transformations(
+    source_path: &String,
+    work_path: &str,
+) -> Result<DestinationConfiguration, String> {
+    let destination_directory = in_work_path(work_path, "destination");
+    let destination_configuration = match get_destination_configuration(&destination_directory) {
+        Err(e) => return Err(e),
+        Ok(c) => c,
     };
-    match git_diff(&source_path) {
+    match apply_dest_path(
+        source_path,
+        &destination_configuration.path,
+        &in_work_path(work_path, "temp"),
+    ) {
+        Err(e) => return Err(e),
+        Ok(_) => {}
+    }
+    match apply_lock_list(
+        source_path,
+        &destination_directory,
+        &destination_configuration.lock_list,
+    ) {
         Err(e) => return Err(e),
+        Ok(_) => {}
+    }
+    Ok(destination_configuration)
+}
+
+pub(crate) fn git_stage(
+    git: &dyn GitBackend,
+    source_path: &str,
+    secret_scan: &SecretScanConfiguration,
+) -> Result<GitDiffResult, SyncError> {
+    match git.add_all(source_path) {
+        Err(e) => return Err(SyncError::Internal(e)),
+        Ok(_) => {}
+    };
+    let changes = match git.diff(source_path) {
+        Err(e) => return Err(SyncError::Internal(e)),
         Ok(diff) => match diff {
             GitDiffResult::NoChanges => return Ok(GitDiffResult::NoChanges),
-            GitDiffResult::Changes => {}
+            GitDiffResult::Changes(c) => c,
         },
     };
-    match git_commit(&source_path) {
+    let staged_files: Vec<String> = changes
+        .iter()
+        .filter(|change| !matches!(change.kind, ChangeKind::Deleted))
+        .map(|change| change.path.clone())
+        .collect();
+    match scan_for_secrets(source_path, &staged_files, secret_scan) {
         Err(e) => return Err(e),
         Ok(_) => {}
     };
-    match git_push(&source_path, dest_repo_url, dest_pat) {
+    Ok(GitDiffResult::Changes(changes))
+}
+
+// The sync branch of a pull request is rebuilt on every run, so it is
+// only pushed again when its content changes.
+pub(crate) fn is_sync_branch_up_to_date(
+    git: &dyn GitBackend,
+    cwd: &str,
+    environment_configuration: &EnvironmentConfiguration,
+    dest_branch: &str,
+) -> bool {
+    if !environment_configuration.pull_request
+        || !git.same_tree(cwd, "HEAD", &format!("origin/{dest_branch}"))
+    {
+        return false;
+    }
+    print_line(&format!(
+        "Sync branch {dest_branch} is already up to date, nothing to push"
+    ));
+    true
+}
+
+pub(crate) fn git_upload(
+    git: &dyn GitBackend,
+    source_path: &String,
+    environment_configuration: &EnvironmentConfiguration,
+    secret_scan: &SecretScanConfiguration,
+    commit_message_template: &str,
+    source_commit: Option<&SourceCommit>,
+    dest_branch: &str,
+) -> Result<GitDiffResult, SyncError> {
+    let changes = match git_stage(git, source_path, secret_scan) {
+        Err(e) => return Err(e),
+        Ok(diff) => match diff {
+            GitDiffResult::NoChanges => return Ok(GitDiffResult::NoChanges),
+            GitDiffResult::Changes(c) => c,
+        },
+    };
+    let commit_message = match render_commit_message(
+        commit_message_template,
+        &CommitMessageContext {
+            source_commit: source_commit,
+            source_ref: environment_configuration.source_ref.as_deref(),
+            timestamp: Utc::now().timestamp(),
+            changes: &changes,
+            include_source_subject: environment_configuration.include_source_subject,
+        },
+    ) {
+        Err(e) => return Err(SyncError::Configuration(e)),
+        Ok(m) => m,
+    };
+    if environment_configuration.dry_run {
+        return Ok(GitDiffResult::Changes(changes));
+    }
+    let identity = default_git_identity(environment_configuration);
+    match git.commit(&source_path, &commit_message, &identity, &identity) {
+        Err(e) => return Err(SyncError::Internal(e)),
+        Ok(_) => {}
+    };
+    if is_sync_branch_up_to_date(git, source_path, environment_configuration, dest_branch) {
+        return Ok(GitDiffResult::NoChanges);
+    }
+    match git.head(&source_path) {
+        Err(e) => return Err(SyncError::Internal(e)),
+        Ok(sha) => report_commit(&sha),
+    };
+    let push_result = git.push(
+        &source_path,
+        &environment_configuration.dest_repo_url,
+        dest_branch,
+        environment_configuration.pull_request,
+    );
+    report_push(
+        dest_branch,
+        environment_configuration.pull_request,
+        push_result.is_ok(),
+    );
+    match push_result {
         Err(e) => return Err(e),
         Ok(_) => {}
     };
-    return Ok(GitDiffResult::Changes);
+    return Ok(GitDiffResult::Changes(changes));
 }
@@ -0,0 +1,95 @@
+use std::env::{temp_dir, var};
+use std::fs::{remove_file, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Destinations synced in parallel each write their own key files.
+static KEY_FILE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+// The key files are deleted when this is dropped,
+// so it has to be kept alive until the last git command has run.
+pub(crate) struct SshKeyFiles {
+    key_path: PathBuf,
+    known_hosts_path: PathBuf,
+    pub ssh_command: String,
+}
+
+impl Drop for SshKeyFiles {
+    fn drop(&mut self) {
+        let _ = remove_file(&self.key_path);
+        let _ = remove_file(&self.known_hosts_path);
+    }
+}
+
+fn write_private_file(path: &Path, contents: &str) -> Result<(), String> {
+    let _ = remove_file(path);
+    let mut file = match OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+    {
+        Err(e) => return Err(format!("Unable to create {}: {e}", path.display())),
+        Ok(f) => f,
+    };
+    // ssh rejects a private key without a trailing newline
+    let contents = if contents.ends_with('\n') {
+        String::from(contents)
+    } else {
+        format!("{contents}\n")
+    };
+    match file.write_all(contents.as_bytes()) {
+        Err(e) => Err(format!("Unable to write {}: {e}", path.display())),
+        Ok(_) => Ok(()),
+    }
+}
+
+// GIT_SSH_COMMAND is interpreted by a shell.
+fn shell_quote(path: &Path) -> String {
+    format!("'{}'", path.display().to_string().replace('\'', "'\\''"))
+}
+
+fn scratch_directory() -> PathBuf {
+    match var("RUNNER_TEMP") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => temp_dir(),
+    }
+}
+
+// Writes the deploy key and known_hosts entries to files readable only by
+// the current user, and returns the ssh command using them.
+// Without known_hosts entries, the host key is accepted on first use and
+// recorded in the scratch known_hosts file, never in ~/.ssh/known_hosts.
+pub(crate) fn write_ssh_key(key: &str, known_hosts: Option<&str>) -> Result<SshKeyFiles, String> {
+    let directory = scratch_directory();
+    let name = format!(
+        "yellow-chameleon-{}-{}",
+        process::id(),
+        KEY_FILE_COUNT.fetch_add(1, Ordering::Relaxed)
+    );
+    let key_path = directory.join(format!("{name}.key"));
+    let known_hosts_path = directory.join(format!("{name}.known_hosts"));
+    let files = SshKeyFiles {
+        ssh_command: format!(
+            "ssh -i {} -o IdentitiesOnly=yes -o BatchMode=yes -o UserKnownHostsFile={} -o StrictHostKeyChecking={}",
+            shell_quote(&key_path),
+            shell_quote(&known_hosts_path),
+            if known_hosts.is_some() { "yes" } else { "accept-new" }
+        ),
+        key_path: key_path,
+        known_hosts_path: known_hosts_path,
+    };
+    // On error, dropping files removes everything written so far.
+    match write_private_file(&files.key_path, key) {
+        Err(e) => return Err(e),
+        Ok(_) => {}
+    }
+    match write_private_file(&files.known_hosts_path, known_hosts.unwrap_or("")) {
+        Err(e) => return Err(e),
+        Ok(_) => {}
+    }
+    Ok(files)
+}
@@ -0,0 +1,121 @@
+use std::env::temp_dir;
+use std::fs::{create_dir_all, remove_dir_all, write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Output};
+
+fn git(cwd: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+        .args(args)
+        .current_dir(cwd)
+        .output()
+        .expect("git is installed");
+    assert!(
+        output.status.success(),
+        "git {args:?} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn write_file(root: &Path, relative_path: &str, contents: &str) {
+    let path = root.join(relative_path);
+    create_dir_all(path.parent().unwrap()).unwrap();
+    write(path, contents).unwrap();
+}
+
+fn run_sync(workspace: &Path, destination_url: &str, git_backend: &str) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_yellow-chameleon"))
+        .current_dir(workspace)
+        .env("CAM_GIT_BACKEND", git_backend)
+        .env("CAM_DEST_REPO", destination_url)
+        .env("CAM_GIT_NAME", "Sync Bot")
+        .env("CAM_GIT_EMAIL", "sync@example.com")
+        .env_remove("GITHUB_ACTIONS")
+        .env_remove("GITHUB_OUTPUT")
+        .env_remove("GITHUB_STEP_SUMMARY")
+        .output()
+        .unwrap()
+}
+
+// Creates a bare destination repository with one commit, as the action
+// expects, and a source repository in the workspace.
+fn set_up(workspace: &Path) -> PathBuf {
+    let destination = workspace.join("destination.git");
+    git(
+        workspace,
+        &["init", "-q", "--bare", "-b", "main", "destination.git"],
+    );
+    let seed = workspace.join("seed");
+    git(workspace, &["init", "-q", "-b", "main", "seed"]);
+    write_file(&seed, "README.md", "destination\n");
+    git(&seed, &["add", "-A"]);
+    git(&seed, &["commit", "-q", "-m", "Initial commit"]);
+    git(
+        &seed,
+        &["push", "-q", destination.to_str().unwrap(), "main"],
+    );
+
+    let source = workspace.join("source");
+    git(workspace, &["init", "-q", "-b", "main", "source"]);
+    write_file(&source, "README.md", "source\n");
+    write_file(&source, "docs/guide.md", "guide\n");
+    write_file(&source, "deploy/key.secret", "hidden\n");
+    write_file(
+        &source,
+        ".yellow-chameleon-source.json",
+        r#"{"ignore": ["*.secret"]}"#,
+    );
+    git(&source, &["add", "-A"]);
+    git(&source, &["commit", "-q", "-m", "Source commit"]);
+    destination
+}
+
+fn sync_into_file_remote(git_backend: &str) {
+    let workspace = temp_dir().join(format!(
+        "yellow-chameleon-test-{}-{git_backend}",
+        process::id()
+    ));
+    let _ = remove_dir_all(&workspace);
+    create_dir_all(&workspace).unwrap();
+    let destination = set_up(&workspace);
+    let destination_url = format!("file://{}", destination.display());
+
+    let output = run_sync(&workspace, &destination_url, git_backend);
+    assert!(
+        output.status.success(),
+        "sync failed: {}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        git(&destination, &["ls-tree", "-r", "--name-only", "main"]),
+        "README.md\ndocs/guide.md\n"
+    );
+    assert_eq!(git(&destination, &["show", "main:README.md"]), "source\n");
+    let log = git(&destination, &["log", "--format=%an %s", "main"]);
+    let subjects: Vec<&str> = log.lines().collect();
+    assert_eq!(subjects.len(), 2);
+    assert!(subjects[0].starts_with("Sync Bot Sync at "));
+    // The source checkout is left as it was.
+    assert_eq!(git(&workspace.join("source"), &["status", "--short"]), "");
+    assert!(!workspace.join("yellow-chameleon-work").exists());
+
+    // A second run finds nothing to sync.
+    let output = run_sync(&workspace, &destination_url, git_backend);
+    assert!(output.status.success());
+    assert_eq!(git(&destination, &["rev-list", "--count", "main"]), "2\n");
+
+    remove_dir_all(&workspace).unwrap();
+}
+
+#[test]
+fn syncs_into_a_file_remote() {
+    sync_into_file_remote("cli");
+}
+
+#[cfg(feature = "native-git")]
+#[test]
+fn syncs_into_a_file_remote_with_the_native_backend() {
+    sync_into_file_remote("native");
+}